@@ -4,18 +4,23 @@
 
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
     runtime::Runtime,
 };
-use tokio_socks::{tcp::socks5::Socks5Stream, Error};
+use tokio_socks::{
+    proxy::{Proxy, ProxyChain},
+    tcp::Socks5Client,
+    Error,
+};
 
 const PROXY_ADDR: [&str; 2] = ["184.176.166.20:4145", "90.89.205.248:1080"]; // public proxies found here : http://spys.one/en/socks-proxy-list/
 const DEST_ADDR: &str = "duckduckgo.com:80";
 
 async fn connect_chained_proxy() -> Result<(), Error> {
-    let proxy_stream = TcpStream::connect(PROXY_ADDR[0]).await?;
-    let chained_proxy_stream = Socks5Stream::connect_with_socket(proxy_stream, PROXY_ADDR[1]).await?;
-    let mut stream = Socks5Stream::connect_with_socket(chained_proxy_stream, DEST_ADDR).await?;
+    let chain = PROXY_ADDR
+        .iter()
+        .map(|addr| Socks5Client::builder(*addr).build().map(Proxy::from))
+        .collect::<Result<ProxyChain, Error>>()?;
+    let mut stream = chain.connect(DEST_ADDR).await?;
 
     stream.write_all(b"GET /\n\n").await?;
 
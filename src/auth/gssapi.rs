@@ -0,0 +1,431 @@
+//! GSSAPI authentication for SOCKS5, as defined in RFC 1961.
+//!
+//! The crate does not link against any GSS-API implementation. Instead, the
+//! security context is driven through the `GssapiMechanism` trait, which can
+//! be implemented on top of a Kerberos library, or faked in tests.
+use std::{fmt, io};
+
+use super::{AuthStep, Encapsulation, Socks5Authenticator};
+use crate::{Error, Result};
+
+const VERSION: u8 = 0x01;
+const MTYP_AUTH: u8 = 0x01;
+const MTYP_PROTECTION: u8 = 0x02;
+const MTYP_ENCAPSULATION: u8 = 0x03;
+const MTYP_ABORT: u8 = 0xff;
+
+/// The outcome of a `GssapiMechanism::init_sec_context` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GssapiStatus {
+    /// `GSS_S_CONTINUE_NEEDED`: the token must be sent to the proxy, whose
+    /// answer is passed to the next call.
+    ContinueNeeded(Vec<u8>),
+    /// `GSS_S_COMPLETE`: the context is established. The token, if not empty,
+    /// is sent to the proxy as the last one, and the proxy's answer is read
+    /// before negotiating the protection level.
+    Complete(Vec<u8>),
+}
+
+/// A GSS-API security context on the client side.
+///
+/// One instance is used for a single connection: it establishes the context
+/// with the proxy, then protects the rest of the connection.
+pub trait GssapiMechanism: Send {
+    /// Calls `GSS_Init_sec_context`, passing the last token received from the
+    /// proxy, or `None` on the first call.
+    fn init_sec_context(&mut self, input: Option<&[u8]>) -> io::Result<GssapiStatus>;
+
+    /// Calls `GSS_Wrap` on `message`, requesting confidentiality if
+    /// `confidential` is set.
+    fn wrap(&mut self, confidential: bool, message: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Calls `GSS_Unwrap` on `token`.
+    fn unwrap(&mut self, token: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// The per-message protection requested once the context is established.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GssapiProtection {
+    /// Every message is integrity protected.
+    Integrity = 0x01,
+    /// Every message is integrity and confidentiality protected.
+    Confidentiality = 0x02,
+}
+
+/// The message expected from the proxy.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Reply {
+    /// A context token, passed to the mechanism.
+    Context,
+    /// The answer to the last context token, once the context is complete.
+    LastContext,
+    /// The protection level.
+    Protection,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Init,
+    SentToken { complete: bool },
+    ReadHeader(Reply),
+    ReadLength(Reply),
+    ReadToken(Reply),
+    SentProtection,
+}
+
+/// The GSSAPI authentication method (`0x01`): context establishment followed
+/// by the protection level negotiation.
+///
+/// On success, the rest of the connection is protected by the mechanism.
+pub struct GssapiAuthenticator {
+    mechanism: Option<Box<dyn GssapiMechanism>>,
+    protection: GssapiProtection,
+    state: State,
+}
+
+impl fmt::Debug for GssapiAuthenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GssapiAuthenticator")
+            .field("protection", &self.protection)
+            .finish()
+    }
+}
+
+impl GssapiAuthenticator {
+    /// Creates an authenticator establishing a context through `mechanism`,
+    /// then requesting the `protection` level.
+    pub fn new<M: GssapiMechanism + 'static>(mechanism: M, protection: GssapiProtection) -> Self {
+        GssapiAuthenticator {
+            mechanism: Some(Box::new(mechanism)),
+            protection,
+            state: State::Init,
+        }
+    }
+
+    fn mechanism(&mut self) -> io::Result<&mut (dyn GssapiMechanism + 'static)> {
+        // The mechanism is only taken away by the final step.
+        self.mechanism
+            .as_deref_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "GSSAPI sub-negotiation is already done"))
+    }
+
+    fn next(&mut self, input: &[u8]) -> Result<AuthStep> {
+        match self.state {
+            State::Init => {
+                let status = self.mechanism()?.init_sec_context(None)?;
+                self.send_token(status)
+            },
+            State::SentToken { complete } => {
+                // The proxy answers every context token, even the last one (RFC 1961 section 3.4).
+                self.state = State::ReadHeader(if complete { Reply::LastContext } else { Reply::Context });
+                Ok(AuthStep::Read(2))
+            },
+            State::SentProtection => {
+                self.state = State::ReadHeader(Reply::Protection);
+                Ok(AuthStep::Read(2))
+            },
+            State::ReadHeader(reply) => {
+                let [version, mtyp] = two_bytes(input)?;
+                if version != VERSION {
+                    return Err(Error::InvalidResponseVersion);
+                }
+                let expected = if reply == Reply::Protection { MTYP_PROTECTION } else { MTYP_AUTH };
+                match mtyp {
+                    MTYP_ABORT => Err(Error::GssapiAuthFailure("rejected by the proxy")),
+                    m if m != expected => Err(Error::GssapiAuthFailure("unexpected message type")),
+                    _ => {
+                        self.state = State::ReadLength(reply);
+                        Ok(AuthStep::Read(2))
+                    },
+                }
+            },
+            State::ReadLength(reply) => {
+                self.state = State::ReadToken(reply);
+                Ok(AuthStep::Read(u16::from_be_bytes(two_bytes(input)?) as usize))
+            },
+            State::ReadToken(Reply::Context) => {
+                let status = self.mechanism()?.init_sec_context(Some(input))?;
+                self.send_token(status)
+            },
+            // The context is already established: there is nothing left to pass the token to.
+            State::ReadToken(Reply::LastContext) => self.send_protection(),
+            State::ReadToken(Reply::Protection) => {
+                let level = self.mechanism()?.unwrap(input)?;
+                let confidential = match level[..] {
+                    [0x01] => false,
+                    [0x02] => true,
+                    _ => return Err(Error::GssapiAuthFailure("unsupported protection level")),
+                };
+                let mechanism = self.mechanism.take().unwrap();
+                Ok(AuthStep::Done(Some(Box::new(GssapiEncapsulation {
+                    mechanism,
+                    confidential,
+                }))))
+            },
+        }
+    }
+
+    fn send_token(&mut self, status: GssapiStatus) -> Result<AuthStep> {
+        let (token, complete) = match status {
+            GssapiStatus::ContinueNeeded(token) => (token, false),
+            GssapiStatus::Complete(token) if token.is_empty() => return self.send_protection(),
+            GssapiStatus::Complete(token) => (token, true),
+        };
+        self.state = State::SentToken { complete };
+        Ok(AuthStep::Send(message(MTYP_AUTH, &token)?))
+    }
+
+    fn send_protection(&mut self) -> Result<AuthStep> {
+        // The protection level is always sent with integrity protection only.
+        let level = self.protection as u8;
+        let token = self.mechanism()?.wrap(false, &[level])?;
+        self.state = State::SentProtection;
+        Ok(AuthStep::Send(message(MTYP_PROTECTION, &token)?))
+    }
+}
+
+impl Socks5Authenticator for GssapiAuthenticator {
+    fn methods(&self) -> &[u8] {
+        &[0x01]
+    }
+
+    fn step(&mut self, _method: u8, input: &[u8]) -> Result<AuthStep> {
+        self.next(input)
+    }
+}
+
+/// Returns the two bytes of `input`, which a step requested.
+fn two_bytes(input: &[u8]) -> io::Result<[u8; 2]> {
+    match *input {
+        [first, second] => Ok([first, second]),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unexpected GSSAPI input length")),
+    }
+}
+
+fn message(mtyp: u8, token: &[u8]) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(4 + token.len());
+    write_message(mtyp, token, &mut buf)?;
+    Ok(buf)
+}
+
+fn write_message(mtyp: u8, token: &[u8], dst: &mut Vec<u8>) -> io::Result<()> {
+    if token.len() > u16::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "GSSAPI token too long"));
+    }
+    dst.extend_from_slice(&[VERSION, mtyp]);
+    dst.extend_from_slice(&(token.len() as u16).to_be_bytes());
+    dst.extend_from_slice(token);
+    Ok(())
+}
+
+/// The protection layer installed once the sub-negotiation succeeded.
+struct GssapiEncapsulation {
+    mechanism: Box<dyn GssapiMechanism>,
+    confidential: bool,
+}
+
+impl Encapsulation for GssapiEncapsulation {
+    fn encode(&mut self, data: &[u8], dst: &mut Vec<u8>) -> io::Result<()> {
+        let token = self.mechanism.wrap(self.confidential, data)?;
+        write_message(MTYP_ENCAPSULATION, &token, dst)
+    }
+
+    fn decode(&mut self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<Option<usize>> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        if src[0] != VERSION || src[1] != MTYP_ENCAPSULATION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid GSSAPI encapsulation header",
+            ));
+        }
+        let len = 4 + u16::from_be_bytes([src[2], src[3]]) as usize;
+        if src.len() < len {
+            return Ok(None);
+        }
+        dst.extend_from_slice(&self.mechanism.unwrap(&src[4..len])?);
+        Ok(Some(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mechanism completing after one round trip, whose wrapping XORs the
+    /// message with a key.
+    struct XorMechanism;
+
+    const KEY: u8 = 0x5a;
+
+    impl GssapiMechanism for XorMechanism {
+        fn init_sec_context(&mut self, input: Option<&[u8]>) -> io::Result<GssapiStatus> {
+            match input {
+                None => Ok(GssapiStatus::ContinueNeeded(b"hello".to_vec())),
+                Some(b"world") => Ok(GssapiStatus::Complete(Vec::new())),
+                Some(_) => Err(io::ErrorKind::PermissionDenied.into()),
+            }
+        }
+
+        fn wrap(&mut self, _confidential: bool, message: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(message.iter().map(|b| b ^ KEY).collect())
+        }
+
+        fn unwrap(&mut self, token: &[u8]) -> io::Result<Vec<u8>> {
+            self.wrap(false, token)
+        }
+    }
+
+    /// A mechanism completing with its first token, like Kerberos without
+    /// mutual authentication.
+    struct OneShotMechanism;
+
+    impl GssapiMechanism for OneShotMechanism {
+        fn init_sec_context(&mut self, input: Option<&[u8]>) -> io::Result<GssapiStatus> {
+            match input {
+                None => Ok(GssapiStatus::Complete(b"ticket".to_vec())),
+                Some(_) => panic!("the context is already complete"),
+            }
+        }
+
+        fn wrap(&mut self, _confidential: bool, message: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(message.to_vec())
+        }
+
+        fn unwrap(&mut self, token: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(token.to_vec())
+        }
+    }
+
+    fn expect_send(step: AuthStep) -> Vec<u8> {
+        match step {
+            AuthStep::Send(message) => message,
+            _ => panic!("expected a message to send"),
+        }
+    }
+
+    fn expect_read(step: AuthStep) -> usize {
+        match step {
+            AuthStep::Read(len) => len,
+            _ => panic!("expected a read"),
+        }
+    }
+
+    #[test]
+    fn sub_negotiation() -> Result<()> {
+        let mut gssapi = GssapiAuthenticator::new(XorMechanism, GssapiProtection::Integrity);
+
+        assert_eq!(expect_send(gssapi.step(0x01, &[])?), b"\x01\x01\x00\x05hello");
+        assert_eq!(expect_read(gssapi.step(0x01, &[])?), 2);
+        assert_eq!(expect_read(gssapi.step(0x01, &[0x01, 0x01])?), 2);
+        assert_eq!(expect_read(gssapi.step(0x01, &[0x00, 0x05])?), 5);
+        assert_eq!(expect_send(gssapi.step(0x01, b"world")?), [
+            0x01,
+            0x02,
+            0x00,
+            0x01,
+            0x01 ^ KEY
+        ]);
+        assert_eq!(expect_read(gssapi.step(0x01, &[])?), 2);
+        assert_eq!(expect_read(gssapi.step(0x01, &[0x01, 0x02])?), 2);
+        assert_eq!(expect_read(gssapi.step(0x01, &[0x00, 0x01])?), 1);
+        let mut codec = match gssapi.step(0x01, &[0x01 ^ KEY])? {
+            AuthStep::Done(Some(codec)) => codec,
+            _ => panic!("expected a protection layer"),
+        };
+
+        let mut wire = Vec::new();
+        codec.encode(b"ping", &mut wire)?;
+        assert_eq!(wire[..4], [0x01, 0x03, 0x00, 0x04]);
+        let mut plain = Vec::new();
+        assert_eq!(codec.decode(&wire[..6], &mut plain)?, None);
+        assert_eq!(codec.decode(&wire, &mut plain)?, Some(8));
+        assert_eq!(plain, b"ping");
+        Ok(())
+    }
+
+    #[test]
+    fn last_context_token_is_answered() -> Result<()> {
+        let mut gssapi = GssapiAuthenticator::new(OneShotMechanism, GssapiProtection::Integrity);
+
+        assert_eq!(expect_send(gssapi.step(0x01, &[])?), b"\x01\x01\x00\x06ticket");
+        assert_eq!(expect_read(gssapi.step(0x01, &[])?), 2);
+        assert_eq!(expect_read(gssapi.step(0x01, &[0x01, 0x01])?), 2);
+        assert_eq!(expect_read(gssapi.step(0x01, &[0x00, 0x00])?), 0);
+        assert_eq!(expect_send(gssapi.step(0x01, &[])?), [0x01, 0x02, 0x00, 0x01, 0x01]);
+        assert_eq!(expect_read(gssapi.step(0x01, &[])?), 2);
+        assert_eq!(expect_read(gssapi.step(0x01, &[0x01, 0x02])?), 2);
+        assert_eq!(expect_read(gssapi.step(0x01, &[0x00, 0x01])?), 1);
+        assert!(matches!(gssapi.step(0x01, &[0x01])?, AuthStep::Done(Some(_))));
+        assert!(matches!(gssapi.step(0x01, &[0x01]), Err(Error::Io(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn aborted_sub_negotiation_should_fail() -> Result<()> {
+        let mut gssapi = GssapiAuthenticator::new(XorMechanism, GssapiProtection::Integrity);
+        expect_send(gssapi.step(0x01, &[])?);
+        expect_read(gssapi.step(0x01, &[])?);
+        assert!(matches!(gssapi.step(0x01, &[0x01]), Err(Error::Io(_))));
+        assert!(matches!(
+            gssapi.step(0x01, &[0x01, 0xff]),
+            Err(Error::GssapiAuthFailure(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn connect_through_protection_layer() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        use crate::tcp::Socks5Client;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (client, mut proxy) = tokio::io::duplex(1024);
+            let proxy = tokio::spawn(async move {
+                let mut buf = [0; 64];
+                proxy.read_exact(&mut buf[..4]).await?;
+                assert_eq!(buf[..4], [0x05, 2, 0x00, 0x01]);
+                proxy.write_all(&[0x05, 0x01]).await?;
+                proxy.read_exact(&mut buf[..9]).await?;
+                assert_eq!(&buf[4..9], b"hello");
+                proxy.write_all(b"\x01\x01\x00\x05world").await?;
+                proxy.read_exact(&mut buf[..5]).await?;
+                proxy.write_all(&[0x01, 0x02, 0x00, 0x01, 0x02 ^ KEY]).await?;
+
+                // The request and reply are encapsulated.
+                proxy.read_exact(&mut buf[..14]).await?;
+                assert_eq!(buf[..4], [0x01, 0x03, 0x00, 10]);
+                let reply: Vec<u8> = [0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 80]
+                    .iter()
+                    .map(|b| b ^ KEY)
+                    .collect();
+                proxy.write_all(&[0x01, 0x03, 0x00, 10]).await?;
+                proxy.write_all(&reply).await?;
+
+                proxy.read_exact(&mut buf[..8]).await?;
+                assert_eq!(buf[..4], [0x01, 0x03, 0x00, 4]);
+                proxy.write_all(&buf[..8]).await?;
+                io::Result::Ok(())
+            });
+
+            let mut stream = Socks5Client::builder("127.0.0.1:1080")
+                .no_auth()
+                .authenticator(|| GssapiAuthenticator::new(XorMechanism, GssapiProtection::Confidentiality))
+                .build()?
+                .connect_with_socket(client, "127.0.0.1:80")
+                .await?;
+            stream.write_all(b"ping").await?;
+            stream.flush().await?;
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"ping");
+            proxy.await.unwrap()?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,174 @@
+//! Authentication methods for SOCKS5 clients.
+//!
+//! Username/password authentication is built into the connectors. Any other
+//! method, including the ones in the private range (`0x80` to `0xFE`), can be
+//! plugged in by implementing `Socks5Authenticator`.
+use std::{fmt, io};
+
+use crate::Result;
+
+pub mod gssapi;
+
+/// A per-message protection layer negotiated during authentication.
+///
+/// Once installed, everything exchanged over the connection, starting with the
+/// SOCKS request, goes through `encode` on the way out and `decode` on the way
+/// in.
+pub trait Encapsulation: Send {
+    /// Wraps `data` into one message and appends it to `dst`.
+    fn encode(&mut self, data: &[u8], dst: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Unwraps the message at the front of `src` and appends its payload to
+    /// `dst`, returning the number of bytes consumed from `src`.
+    ///
+    /// Returns `None` if `src` does not hold a complete message yet.
+    fn decode(&mut self, src: &[u8], dst: &mut Vec<u8>) -> io::Result<Option<usize>>;
+}
+
+/// The next thing an authentication sub-negotiation needs from the connection.
+pub enum AuthStep {
+    /// Send these bytes to the proxy, then step again with no input.
+    Send(Vec<u8>),
+    /// Read exactly this many bytes from the proxy, then step again with them.
+    Read(usize),
+    /// The sub-negotiation succeeded, optionally installing a protection
+    /// layer.
+    Done(Option<Box<dyn Encapsulation>>),
+}
+
+impl fmt::Debug for AuthStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthStep::Send(message) => f.debug_tuple("Send").field(message).finish(),
+            AuthStep::Read(len) => f.debug_tuple("Read").field(len).finish(),
+            AuthStep::Done(layer) => f.debug_tuple("Done").field(&layer.is_some()).finish(),
+        }
+    }
+}
+
+/// A SOCKS5 authentication method, run by the connector once the proxy
+/// selected it.
+///
+/// The sub-negotiation is written as a sequence of steps over the raw
+/// connection to the proxy: each call to `step` tells the connector what to
+/// send or how much to read next, and the connector performs the I/O on the
+/// socket. This keeps authenticators independent of the socket type and of
+/// the async runtime.
+///
+/// An instance is used for a single connection, so it may keep the state of
+/// the sub-negotiation in `self`.
+pub trait Socks5Authenticator: Send {
+    /// The method IDs offered to the proxy, in order of preference.
+    ///
+    /// Neither `0x00` (no authentication) nor `0xFF` may be offered here.
+    fn methods(&self) -> &[u8];
+
+    /// Advances the sub-negotiation of `method`, which the proxy selected
+    /// among `methods`.
+    ///
+    /// The first call receives no input. Each following call receives the
+    /// bytes requested by `AuthStep::Read`, or nothing after `AuthStep::Send`.
+    fn step(&mut self, method: u8, input: &[u8]) -> Result<AuthStep>;
+}
+
+impl fmt::Debug for dyn Socks5Authenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Authenticator")
+            .field("methods", &self.methods())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{tcp::Socks5Client, Error};
+
+    /// A private method sending a token and expecting a one byte status.
+    struct TokenAuthenticator {
+        sent: bool,
+    }
+
+    impl Socks5Authenticator for TokenAuthenticator {
+        fn methods(&self) -> &[u8] {
+            &[0x80]
+        }
+
+        fn step(&mut self, method: u8, input: &[u8]) -> Result<AuthStep> {
+            assert_eq!(method, 0x80);
+            if !self.sent {
+                self.sent = true;
+                return Ok(AuthStep::Send(b"token".to_vec()));
+            }
+            match input {
+                [] => Ok(AuthStep::Read(1)),
+                [0x00] => Ok(AuthStep::Done(None)),
+                _ => Err(Error::Io(io::ErrorKind::PermissionDenied.into())),
+            }
+        }
+    }
+
+    #[test]
+    fn private_method() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (client, mut proxy) = tokio::io::duplex(1024);
+            let proxy = tokio::spawn(async move {
+                let mut buf = [0; 10];
+                proxy.read_exact(&mut buf[..4]).await?;
+                assert_eq!(buf[..4], [0x05, 2, 0x00, 0x80]);
+                proxy.write_all(&[0x05, 0x80]).await?;
+                proxy.read_exact(&mut buf[..5]).await?;
+                assert_eq!(&buf[..5], b"token");
+                proxy.write_all(&[0x00]).await?;
+                proxy.read_exact(&mut buf).await?;
+                proxy.write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 80]).await?;
+                io::Result::Ok(())
+            });
+
+            let stream = Socks5Client::builder("127.0.0.1:1080")
+                .no_auth()
+                .authenticator(|| TokenAuthenticator { sent: false })
+                .build()?
+                .connect_with_socket(client, "127.0.0.1:80")
+                .await?;
+            assert_eq!(stream.target_addr().to_string(), "127.0.0.1:80");
+            assert_eq!(stream.auth_method(), 0x80);
+            proxy.await.unwrap()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn proxy_selects_among_offered_methods() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (client, mut proxy) = tokio::io::duplex(1024);
+            let proxy = tokio::spawn(async move {
+                let mut buf = [0; 10];
+                proxy.read_exact(&mut buf[..5]).await?;
+                assert_eq!(buf[..5], [0x05, 3, 0x80, 0x02, 0x00]);
+                proxy.write_all(&[0x05, 0x02]).await?;
+                proxy.read_exact(&mut buf[..7]).await?;
+                assert_eq!(buf[..7], [0x01, 2, b'm', b'e', 2, b'p', b'w']);
+                proxy.write_all(&[0x01, 0x00]).await?;
+                proxy.read_exact(&mut buf).await?;
+                proxy.write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 80]).await?;
+                io::Result::Ok(())
+            });
+
+            let stream = Socks5Client::builder("127.0.0.1:1080")
+                .authenticator(|| TokenAuthenticator { sent: false })
+                .password("me", "pw")
+                .no_auth()
+                .build()?
+                .connect_with_socket(client, "127.0.0.1:80")
+                .await?;
+            assert_eq!(stream.auth_method(), 0x02);
+            proxy.await.unwrap()?;
+            Ok(())
+        })
+    }
+}
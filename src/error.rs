@@ -1,3 +1,7 @@
+use std::{error, fmt, io, net::SocketAddr};
+
+use crate::TargetAddr;
+
 /// Error type of `tokio-socks`
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -11,9 +15,32 @@ pub enum Error {
     /// message.
     #[error("Target address is invalid: {0}")]
     InvalidTargetAddress(&'static str),
-    /// Proxy server unreachable.
-    #[error("Proxy server unreachable")]
-    ProxyServerUnreachable,
+    /// The domain name of the target couldn't be resolved locally.
+    #[error("Target resolution failed: {0}")]
+    TargetResolutionFailure(#[source] io::Error),
+    /// Invalid proxy URL. It contains the detailed error message.
+    #[error("Proxy URL is invalid: {0}")]
+    InvalidProxyUrl(&'static str),
+    /// A SOCKS message couldn't be decoded.
+    #[error("Invalid message: {0}")]
+    InvalidMessage(#[from] DecodeError),
+
+    /// Proxy server unreachable. It contains every failed connection attempt.
+    #[error("Proxy server unreachable: {0}")]
+    ProxyServerUnreachable(#[source] ProxyUnreachable),
+    /// A hop of a proxy chain failed.
+    #[error("Proxy chain hop {hop} ({proxy}) failed: {source}")]
+    ProxyChainHopFailure {
+        /// The index of the hop in the chain, starting from 0.
+        hop: usize,
+        /// The address of the proxy of the hop.
+        proxy: TargetAddr<'static>,
+        /// The error of the connection to the proxy or of the handshake with
+        /// it.
+        #[source]
+        source: Box<Error>,
+    },
+
     /// Proxy server returns an invalid version number.
     #[error("Invalid response version")]
     InvalidResponseVersion,
@@ -62,6 +89,9 @@ pub enum Error {
     /// Password auth failure
     #[error("Password auth failure, code: {0}")]
     PasswordAuthFailure(u8),
+    /// GSSAPI auth failure. It contains the detailed error message.
+    #[error("GSSAPI auth failure: {0}")]
+    GssapiAuthFailure(&'static str),
 
     #[error("Authorization required")]
     AuthorizationRequired,
@@ -71,6 +101,101 @@ pub enum Error {
 
     #[error("Request rejected because the client program and identd report different user-ids")]
     InvalidUserIdAuthFailure,
+
+    /// The TCP connection to the proxy server timed out.
+    #[error("Connection to the proxy server timed out")]
+    ConnectTimeout,
+    /// The method selection or the authentication sub-negotiation timed out.
+    #[error("Authentication timed out")]
+    AuthTimeout,
+    /// The reply to the command timed out.
+    #[error("Reply timed out")]
+    ReplyTimeout,
+
+    /// The datagram needs more fragments than the SOCKS5 UDP header allows.
+    #[error("Datagram too large")]
+    DatagramTooLarge,
+
+    /// An htpasswd file is malformed.
+    #[error("Invalid htpasswd file, line {line}: {reason}")]
+    InvalidHtpasswd {
+        /// The line of the malformed entry, starting at 1.
+        line: usize,
+        /// What is wrong with the entry.
+        reason: &'static str,
+    },
+
+    /// An access control list is malformed.
+    #[error("Invalid access control list: {0}")]
+    InvalidAcl(String),
+}
+
+/// The failed attempts to connect to a proxy server, in the order they were
+/// made.
+#[derive(Debug, Default)]
+pub struct ProxyUnreachable {
+    attempts: Vec<(SocketAddr, io::Error)>,
+}
+
+impl ProxyUnreachable {
+    #[cfg(feature = "tokio")]
+    pub(crate) fn push(&mut self, addr: SocketAddr, err: io::Error) {
+        self.attempts.push((addr, err));
+    }
+
+    /// Returns the address of the last attempt, if the proxy resolved to any.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.attempts.last().map(|(addr, _)| *addr)
+    }
+
+    /// Returns every attempted address along with the error it failed with.
+    pub fn attempts(&self) -> &[(SocketAddr, io::Error)] {
+        &self.attempts
+    }
+}
+
+impl fmt::Display for ProxyUnreachable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.attempts.is_empty() {
+            return f.write_str("no address to connect to");
+        }
+        for (i, (addr, err)) in self.attempts.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} ({})", addr, err)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ProxyUnreachable {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.attempts.last().map(|(_, err)| err as _)
+    }
+}
+
+/// The reason a SOCKS message couldn't be decoded.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The message is truncated. It contains the length it has at least.
+    #[error("incomplete message, at least {0} bytes needed")]
+    Incomplete(usize),
+    /// The version number is not the one of the message.
+    #[error("invalid version {0:#04x}")]
+    InvalidVersion(u8),
+    /// A reserved byte is not zero.
+    #[error("invalid reserved byte {0:#04x}")]
+    InvalidReservedByte(u8),
+    /// The command is unknown.
+    #[error("unknown command {0:#04x}")]
+    UnknownCommand(u8),
+    /// The address type is unknown.
+    #[error("unknown address type {0:#04x}")]
+    UnknownAddressType(u8),
+    /// A domain name, username, password or user ID is not valid UTF-8.
+    #[error("string is not valid UTF-8")]
+    InvalidUtf8,
 }
 
 ///// Result type of `tokio-socks`
@@ -1,3 +1,5 @@
+#[cfg(feature = "async-io")]
+mod async_io;
 #[cfg(feature = "futures-io")]
 mod futures;
 
@@ -14,6 +16,23 @@ mod futures;
 ///     Socks5Stream::connect_with_password_and_socket(socket, target, username, password).await?;
 /// // Socks5Stream has implemented futures-io AsyncRead + AsyncWrite.
 /// ```
+///
+/// With the `async-io` feature, `Compat` also implements
+/// `AsyncDatagramSocket` for `async-io` UDP sockets:
+/// ```no_run
+/// # async fn run(proxy_addr: &str) -> tokio_socks::Result<()> {
+/// use std::sync::Arc;
+///
+/// use async_io::Async;
+/// use smol::net::{TcpStream, UdpSocket};
+/// use tokio_socks::{io::Compat, udp::Socks5Datagram};
+/// let socket = Compat::new(TcpStream::connect(proxy_addr).await?);
+/// let udp = UdpSocket::bind("0.0.0.0:0").await?;
+/// let udp = Compat::new(Arc::<Async<std::net::UdpSocket>>::from(udp));
+/// let datagram = Socks5Datagram::bind_with_socket(socket, udp).await?;
+/// # Ok(())
+/// # }
+/// ```
 pub struct Compat<S>(S);
 
 #[cfg(feature = "futures-io")]
@@ -0,0 +1,40 @@
+use std::{
+    borrow::Borrow,
+    io::{ErrorKind, Result as IoResult},
+    net::{SocketAddr, UdpSocket},
+    task::{Context, Poll},
+};
+
+use async_io::Async;
+use futures_util::ready;
+
+use super::Compat;
+use crate::io::AsyncDatagramSocket;
+
+impl<S> AsyncDatagramSocket for Compat<S>
+where S: Borrow<Async<UdpSocket>>
+{
+    fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<IoResult<usize>> {
+        let socket = self.0.borrow();
+        loop {
+            match socket.get_ref().send_to(buf, target) {
+                Err(err) if err.kind() == ErrorKind::WouldBlock => ready!(socket.poll_writable(cx))?,
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<IoResult<(usize, SocketAddr)>> {
+        let socket = self.0.borrow();
+        loop {
+            match socket.get_ref().recv_from(buf) {
+                Err(err) if err.kind() == ErrorKind::WouldBlock => ready!(socket.poll_readable(cx))?,
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        self.0.borrow().get_ref().local_addr()
+    }
+}
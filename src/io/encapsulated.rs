@@ -0,0 +1,179 @@
+//! Framing of a connection through the protection layer negotiated during
+//! authentication.
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+    mem,
+    pin::Pin,
+    sync::{Mutex, PoisonError},
+    task::{Context, Poll},
+};
+
+use futures_util::ready;
+
+use super::AsyncSocket;
+use crate::auth::Encapsulation;
+
+/// The largest amount of data wrapped into a single message.
+const MAX_MESSAGE_LEN: usize = 16 * 1024;
+
+const READ_CHUNK_LEN: usize = 8 * 1024;
+
+/// The buffers of a connection going through an `Encapsulation`.
+///
+/// Written data is wrapped and buffered until the inner socket accepts it, and
+/// read data is buffered until a whole message can be unwrapped.
+pub(crate) struct Encapsulated {
+    // Only ever accessed through `&mut self`, the lock merely keeps the
+    // connection `Sync`.
+    codec: Mutex<Box<dyn Encapsulation>>,
+    read_wire: Vec<u8>,
+    read_plain: Vec<u8>,
+    read_pos: usize,
+    write_wire: Vec<u8>,
+    write_pos: usize,
+}
+
+impl fmt::Debug for Encapsulated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encapsulated").finish_non_exhaustive()
+    }
+}
+
+impl Encapsulated {
+    pub(crate) fn new(codec: Box<dyn Encapsulation>) -> Self {
+        Encapsulated {
+            codec: Mutex::new(codec),
+            read_wire: Vec::new(),
+            read_plain: Vec::new(),
+            read_pos: 0,
+            write_wire: Vec::new(),
+            write_pos: 0,
+        }
+    }
+
+    fn codec(&mut self) -> &mut dyn Encapsulation {
+        &mut **self.codec.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Reads unwrapped data, using `read` to pull bytes from the inner socket.
+    pub(crate) fn poll_read<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+        mut read: R,
+    ) -> Poll<Result<usize, Error>>
+    where
+        R: FnMut(&mut Context<'_>, &mut [u8]) -> Poll<Result<usize, Error>>,
+    {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        loop {
+            if self.read_pos < self.read_plain.len() {
+                let plain = &self.read_plain[self.read_pos..];
+                let n = plain.len().min(buf.len());
+                buf[..n].copy_from_slice(&plain[..n]);
+                self.read_pos += n;
+                return Poll::Ready(Ok(n));
+            }
+
+            let mut plain = mem::take(&mut self.read_plain);
+            plain.clear();
+            self.read_pos = 0;
+            let wire = mem::take(&mut self.read_wire);
+            let decoded = self.codec().decode(&wire, &mut plain);
+            self.read_wire = wire;
+            self.read_plain = plain;
+            if let Some(consumed) = decoded? {
+                self.read_wire.drain(..consumed);
+                continue;
+            }
+
+            let mut chunk = [0; READ_CHUNK_LEN];
+            let n = ready!(read(cx, &mut chunk))?;
+            if n == 0 {
+                if self.read_wire.is_empty() {
+                    return Poll::Ready(Ok(0));
+                }
+                return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()));
+            }
+            self.read_wire.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Wraps and buffers data, using `write` to push bytes to the inner
+    /// socket.
+    ///
+    /// The data is accepted as soon as it is wrapped, `poll_flush` must be
+    /// called to make sure it reached the inner socket.
+    pub(crate) fn poll_write<W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        mut write: W,
+    ) -> Poll<Result<usize, Error>>
+    where
+        W: FnMut(&mut Context<'_>, &[u8]) -> Poll<Result<usize, Error>>,
+    {
+        ready!(self.poll_flush(cx, &mut write))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let n = buf.len().min(MAX_MESSAGE_LEN);
+        let mut wire = mem::take(&mut self.write_wire);
+        let encoded = self.codec().encode(&buf[..n], &mut wire);
+        self.write_wire = wire;
+        encoded?;
+
+        if let Poll::Ready(Err(err)) = self.poll_flush(cx, &mut write) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    /// Pushes the buffered wrapped data to the inner socket.
+    pub(crate) fn poll_flush<W>(&mut self, cx: &mut Context<'_>, mut write: W) -> Poll<Result<(), Error>>
+    where W: FnMut(&mut Context<'_>, &[u8]) -> Poll<Result<usize, Error>> {
+        while self.write_pos < self.write_wire.len() {
+            let n = ready!(write(cx, &self.write_wire[self.write_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += n;
+        }
+        self.write_wire.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// An `AsyncSocket` going through the protection layer, used to finish the
+/// handshake once authentication installed one.
+///
+/// Unlike `Encapsulated`, it pushes buffered data out before reading, since
+/// `AsyncSocket` has no way to flush.
+pub(crate) struct EncapsulatedSocket<'a, S> {
+    pub(crate) socket: &'a mut S,
+    pub(crate) layer: &'a mut Encapsulated,
+}
+
+impl<S: AsyncSocket + Unpin> AsyncSocket for EncapsulatedSocket<'_, S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize, Error>> {
+        let this = &mut *self;
+        let socket = &mut *this.socket;
+        ready!(this
+            .layer
+            .poll_flush(cx, |cx, buf| Pin::new(&mut *socket).poll_write(cx, buf)))?;
+        this.layer
+            .poll_read(cx, buf, |cx, buf| Pin::new(&mut *socket).poll_read(cx, buf))
+    }
+
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, Error>> {
+        let this = &mut *self;
+        let socket = &mut *this.socket;
+        this.layer
+            .poll_write(cx, buf, |cx, buf| Pin::new(&mut *socket).poll_write(cx, buf))
+    }
+}
@@ -1,5 +1,6 @@
 //! Asynchronous I/O abstractions for sockets.
 
+mod encapsulated;
 #[cfg(feature = "tokio")]
 mod tokio;
 
@@ -7,12 +8,15 @@ use std::{
     future::Future,
     io::{Error, ErrorKind},
     mem,
+    net::SocketAddr,
     pin::Pin,
     task::{Context, Poll},
 };
 
 use futures_util::ready;
 
+pub(crate) use self::encapsulated::{Encapsulated, EncapsulatedSocket};
+
 #[cfg(feature = "futures-io")]
 mod compat;
 #[cfg(feature = "futures-io")]
@@ -30,6 +34,20 @@ pub trait AsyncSocket {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, Error>>;
 }
 
+/// A trait for asynchronous datagram socket I/O.
+///
+/// tokio's `UdpSocket` has implemented `AsyncDatagramSocket` trait.
+///
+/// Use `Compat` to wrap `async-io` UDP sockets (which back smol's
+/// `UdpSocket`) as `AsyncDatagramSocket` types.
+pub trait AsyncDatagramSocket {
+    fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<Result<usize, Error>>;
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<(usize, SocketAddr), Error>>;
+
+    fn local_addr(&self) -> Result<SocketAddr, Error>;
+}
+
 pub(crate) trait AsyncSocketExt {
     fn read_exact<'a>(&'a mut self, buf: &'a mut [u8]) -> ReadExact<'a, Self>
     where Self: Sized;
@@ -104,3 +122,51 @@ impl<W: AsyncSocket + Unpin> Future for WriteAll<'_, W> {
         Poll::Ready(Ok(()))
     }
 }
+
+pub(crate) trait AsyncDatagramSocketExt {
+    fn send_to<'a>(&'a self, buf: &'a [u8], target: SocketAddr) -> SendTo<'a, Self>;
+
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> RecvFrom<'a, Self>;
+}
+
+impl<S: AsyncDatagramSocket> AsyncDatagramSocketExt for S {
+    fn send_to<'a>(&'a self, buf: &'a [u8], target: SocketAddr) -> SendTo<'a, Self> {
+        SendTo {
+            socket: self,
+            buf,
+            target,
+        }
+    }
+
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> RecvFrom<'a, Self> {
+        RecvFrom { socket: self, buf }
+    }
+}
+
+pub(crate) struct SendTo<'a, S: ?Sized> {
+    socket: &'a S,
+    buf: &'a [u8],
+    target: SocketAddr,
+}
+
+impl<S: AsyncDatagramSocket> Future for SendTo<'_, S> {
+    type Output = Result<usize, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.socket.poll_send_to(cx, self.buf, self.target)
+    }
+}
+
+pub(crate) struct RecvFrom<'a, S: ?Sized> {
+    socket: &'a S,
+    buf: &'a mut [u8],
+}
+
+impl<S: AsyncDatagramSocket> Future for RecvFrom<'_, S> {
+    type Output = Result<(usize, SocketAddr), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.socket.poll_recv_from(cx, this.buf)
+    }
+}
@@ -1,15 +1,20 @@
 //! AsyncSocket trait implementation for tokio's AsyncRead + AsyncWrite
-//! traits.
+//! traits, and AsyncDatagramSocket trait implementation for tokio's
+//! UdpSocket.
 use std::{
     io::Result as IoResult,
+    net::SocketAddr,
     pin::Pin,
     task::{Context, Poll},
 };
 
 use futures_util::ready;
-use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::UdpSocket,
+};
 
-use super::AsyncSocket;
+use super::{AsyncDatagramSocket, AsyncSocket};
 
 impl<S> AsyncSocket for S
 where S: AsyncRead + AsyncWrite
@@ -24,3 +29,19 @@ where S: AsyncRead + AsyncWrite
         AsyncWrite::poll_write(self, cx, buf)
     }
 }
+
+impl AsyncDatagramSocket for UdpSocket {
+    fn poll_send_to(&self, cx: &mut Context<'_>, buf: &[u8], target: SocketAddr) -> Poll<IoResult<usize>> {
+        UdpSocket::poll_send_to(self, cx, buf, target)
+    }
+
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<IoResult<(usize, SocketAddr)>> {
+        let mut buf = ReadBuf::new(buf);
+        let addr = ready!(UdpSocket::poll_recv_from(self, cx, &mut buf))?;
+        Poll::Ready(Ok((buf.filled().len(), addr)))
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
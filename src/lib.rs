@@ -9,9 +9,10 @@ use std::{
 };
 
 use either::Either;
-pub use error::Error;
+pub use error::{DecodeError, Error, ProxyUnreachable};
 use futures_util::{
     future,
+    ready,
     stream::{self, Once, Stream},
 };
 
@@ -52,8 +53,7 @@ impl ToProxyAddrs for &[SocketAddr] {
     type Output = ProxyAddrsStream;
 
     fn to_proxy_addrs(&self) -> Self::Output {
-        let addrs = self.to_vec();
-        ProxyAddrsStream(Some(IoResult::Ok(addrs.into_iter())))
+        ProxyAddrsStream::ready(Ok(self.to_vec()))
     }
 }
 
@@ -61,7 +61,7 @@ impl ToProxyAddrs for str {
     type Output = ProxyAddrsStream;
 
     fn to_proxy_addrs(&self) -> Self::Output {
-        ProxyAddrsStream(Some(self.to_socket_addrs()))
+        resolve::resolve_host_port(&resolve::DefaultResolver::default(), self)
     }
 }
 
@@ -69,7 +69,7 @@ impl ToProxyAddrs for (&str, u16) {
     type Output = ProxyAddrsStream;
 
     fn to_proxy_addrs(&self) -> Self::Output {
-        ProxyAddrsStream(Some(self.to_socket_addrs()))
+        resolve::resolve_host(&resolve::DefaultResolver::default(), self.0, self.1)
     }
 }
 
@@ -81,19 +81,42 @@ impl<T: ToProxyAddrs + ?Sized> ToProxyAddrs for &T {
     }
 }
 
-pub struct ProxyAddrsStream(Option<IoResult<vec::IntoIter<SocketAddr>>>);
+/// A stream of the addresses of a proxy server, which may be resolved
+/// asynchronously.
+pub struct ProxyAddrsStream(ProxyAddrsState);
+
+enum ProxyAddrsState {
+    Resolving(resolve::ResolveFuture),
+    Resolved(vec::IntoIter<SocketAddr>),
+    Failed(Option<std::io::Error>),
+}
+
+impl ProxyAddrsStream {
+    pub(crate) fn ready(addrs: IoResult<Vec<SocketAddr>>) -> Self {
+        match addrs {
+            Ok(addrs) => ProxyAddrsStream(ProxyAddrsState::Resolved(addrs.into_iter())),
+            Err(err) => ProxyAddrsStream(ProxyAddrsState::Failed(Some(err))),
+        }
+    }
+
+    pub(crate) fn resolving(future: resolve::ResolveFuture) -> Self {
+        ProxyAddrsStream(ProxyAddrsState::Resolving(future))
+    }
+}
 
 impl Stream for ProxyAddrsStream {
     type Item = Result<SocketAddr>;
 
-    fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.0.as_mut() {
-            Some(Ok(iter)) => Poll::Ready(iter.next().map(Result::Ok)),
-            Some(Err(_)) => {
-                let err = self.0.take().unwrap().unwrap_err();
-                Poll::Ready(Some(Err(err.into())))
-            },
-            None => unreachable!(),
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match &mut self.0 {
+                ProxyAddrsState::Resolving(future) => {
+                    let addrs = ready!(future.as_mut().poll(cx));
+                    *self = ProxyAddrsStream::ready(addrs);
+                },
+                ProxyAddrsState::Resolved(iter) => return Poll::Ready(iter.next().map(Result::Ok)),
+                ProxyAddrsState::Failed(err) => return Poll::Ready(err.take().map(|err| Err(err.into()))),
+            }
         }
     }
 }
@@ -255,24 +278,40 @@ where
 }
 
 /// Authentication methods
+///
+/// A list of them can be offered to a SOCKS5 proxy, which then selects the one
+/// to run.
 #[derive(Debug)]
-enum Authentication<'a> {
+pub enum Authentication<'a> {
+    /// Username/password authentication (`0x02`).
     Password { username: &'a str, password: &'a str },
+    /// A custom authentication method, offering the methods of the
+    /// authenticator.
+    Custom(Box<dyn auth::Socks5Authenticator>),
+    /// No authentication (`0x00`).
     None,
 }
 
 impl Authentication<'_> {
-    fn id(&self) -> u8 {
+    /// Returns the SOCKS5 method IDs offered by this authentication method.
+    pub fn methods(&self) -> &[u8] {
         match self {
-            Authentication::Password { .. } => 0x02,
-            Authentication::None => 0x00,
+            Authentication::Password { .. } => &[0x02],
+            Authentication::Custom(authenticator) => authenticator.methods(),
+            Authentication::None => &[0x00],
         }
     }
 }
 
+pub mod auth;
 mod error;
 pub mod io;
+pub mod proxy;
+pub mod resolve;
+pub mod server;
 pub mod tcp;
+pub mod time;
+pub mod udp;
 
 #[cfg(test)]
 mod tests {
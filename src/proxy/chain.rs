@@ -0,0 +1,249 @@
+use std::{
+    fmt,
+    io,
+    iter::FromIterator,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::{connect_directly, Proxy, ProxyStream};
+use crate::{Error, IntoTargetAddr, Result, TargetAddr};
+
+/// An ordered list of proxies, each one reached through the previous ones.
+///
+/// The stream it yields is the one of the last hop, whichever the number of
+/// hops, with the streams of the previous hops hidden in a `ChainSocket`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # async fn connect() -> tokio_socks::Result<()> {
+/// use tokio_socks::proxy::{Proxy, ProxyChain};
+///
+/// let chain = ProxyChain::new()
+///     .hop("socks5://proxy1.example.com".parse::<Proxy>()?)
+///     .hop("socks4a://proxy2.example.com".parse::<Proxy>()?);
+/// let stream = chain.connect("example.com:80").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ProxyChain {
+    hops: Vec<Proxy>,
+}
+
+impl ProxyChain {
+    /// Creates an empty chain, which connects directly.
+    pub fn new() -> Self {
+        ProxyChain::default()
+    }
+
+    /// Appends a hop to the chain.
+    pub fn hop<P: Into<Proxy>>(mut self, proxy: P) -> Self {
+        self.hops.push(proxy.into());
+        self
+    }
+
+    /// Returns the hops of the chain, in order.
+    pub fn hops(&self) -> &[Proxy] {
+        &self.hops
+    }
+
+    /// Connects to a target server through every hop of the chain.
+    ///
+    /// # Error
+    ///
+    /// A failure of the connection to the first proxy, or of the handshake
+    /// with any proxy, is reported as `Error::ProxyChainHopFailure`.
+    pub async fn connect<'t, T>(&self, target: T) -> Result<ProxyStream<ChainSocket>>
+    where T: IntoTargetAddr<'t> {
+        let target = target.into_target_addr()?;
+        let socket = match self.hops.first() {
+            Some(first) => first
+                .connect_to_proxy()
+                .await
+                .map_err(|err| hop_failure(0, first, err))?,
+            None => connect_directly(&target).await?,
+        };
+        self.connect_through(ChainSocket::new(socket), target).await
+    }
+
+    /// Connects to a target server through every hop of the chain, given a
+    /// socket to the first proxy, or to the target if the chain is empty.
+    ///
+    /// # Error
+    ///
+    /// A failure of the handshake with any proxy is reported as
+    /// `Error::ProxyChainHopFailure`.
+    pub async fn connect_with_socket<'t, S, T>(&self, socket: S, target: T) -> Result<ProxyStream<ChainSocket>>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        T: IntoTargetAddr<'t>,
+    {
+        self.connect_through(ChainSocket::new(socket), target.into_target_addr()?)
+            .await
+    }
+
+    async fn connect_through(
+        &self,
+        mut socket: ChainSocket,
+        target: TargetAddr<'_>,
+    ) -> Result<ProxyStream<ChainSocket>> {
+        let (last, hops) = match self.hops.split_last() {
+            Some(split) => split,
+            None => {
+                return Ok(ProxyStream::Direct {
+                    socket,
+                    target: target.to_owned(),
+                })
+            },
+        };
+        // Each proxy connects to the next one, and the last one to the target.
+        for (hop, proxy) in hops.iter().enumerate() {
+            let stream = proxy
+                .connect_with_socket(socket, self.hops[hop + 1].proxy_addr())
+                .await
+                .map_err(|err| hop_failure(hop, proxy, err))?;
+            socket = ChainSocket::new(stream);
+        }
+        last.connect_with_socket(socket, target)
+            .await
+            .map_err(|err| hop_failure(hops.len(), last, err))
+    }
+}
+
+impl FromIterator<Proxy> for ProxyChain {
+    fn from_iter<I: IntoIterator<Item = Proxy>>(iter: I) -> Self {
+        ProxyChain {
+            hops: iter.into_iter().collect(),
+        }
+    }
+}
+
+fn hop_failure(hop: usize, proxy: &Proxy, err: Error) -> Error {
+    Error::ProxyChainHopFailure {
+        hop,
+        proxy: proxy.proxy_addr().to_owned(),
+        source: Box::new(err),
+    }
+}
+
+trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+/// The connection carrying the last hop of a `ProxyChain`, which hides the
+/// streams of the previous hops.
+pub struct ChainSocket(Box<dyn Io>);
+
+impl ChainSocket {
+    fn new<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(socket: S) -> Self {
+        ChainSocket(Box::new(socket))
+    }
+}
+
+impl fmt::Debug for ChainSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChainSocket").finish_non_exhaustive()
+    }
+}
+
+impl AsyncRead for ChainSocket {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ChainSocket {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[test]
+    fn hops_are_chained() -> Result<()> {
+        let chain = ProxyChain::new()
+            .hop("socks5://127.0.0.1:1080".parse::<Proxy>()?)
+            .hop("socks4a://10.0.0.1:1081".parse::<Proxy>()?);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (socket, mut proxy) = tokio::io::duplex(1024);
+            let proxy = tokio::spawn(async move {
+                // The first hop connects to the second one.
+                let mut buf = [0; 10];
+                proxy.read_exact(&mut buf[..3]).await?;
+                assert_eq!(buf[..3], [0x05, 1, 0x00]);
+                proxy.write_all(&[0x05, 0x00]).await?;
+                proxy.read_exact(&mut buf).await?;
+                assert_eq!(buf, [0x05, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0x04, 0x39]);
+                proxy
+                    .write_all(&[0x05, 0x00, 0x00, 0x01, 10, 0, 0, 1, 0x04, 0x39])
+                    .await?;
+
+                // The second hop connects to the target.
+                let mut buf = [0; 21];
+                proxy.read_exact(&mut buf).await?;
+                assert_eq!(buf[..9], [0x04, 0x01, 0, 80, 0, 0, 0, 1, 0]);
+                assert_eq!(&buf[9..], b"example.com\0");
+                proxy.write_all(&[0x00, 0x5a, 0, 0, 0, 0, 0, 0]).await?;
+                proxy.read_u8().await
+            });
+
+            let mut stream = chain.connect_with_socket(socket, "example.com:80").await?;
+            stream.write_u8(42).await?;
+            assert_eq!(proxy.await.unwrap()?, 42);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn failing_hop_is_reported() -> Result<()> {
+        let chain = ProxyChain::new()
+            .hop("socks5://127.0.0.1:1080".parse::<Proxy>()?)
+            .hop("socks5h://10.0.0.1:1081".parse::<Proxy>()?);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (socket, mut proxy) = tokio::io::duplex(1024);
+            let proxy = tokio::spawn(async move {
+                let mut buf = [0; 10];
+                proxy.read_exact(&mut buf[..3]).await?;
+                proxy.write_all(&[0x05, 0x00]).await?;
+                proxy.read_exact(&mut buf).await?;
+                proxy
+                    .write_all(&[0x05, 0x00, 0x00, 0x01, 10, 0, 0, 1, 0x04, 0x39])
+                    .await?;
+                proxy.read_exact(&mut buf[..3]).await?;
+                proxy.write_all(&[0x05, 0xff]).await
+            });
+
+            match chain.connect_with_socket(socket, "example.com:80").await {
+                Err(Error::ProxyChainHopFailure { hop, proxy, source }) => {
+                    assert_eq!(hop, 1);
+                    assert_eq!(proxy.to_string(), "10.0.0.1:1081");
+                    assert!(matches!(*source, Error::NoAcceptableAuthMethods));
+                },
+                res => panic!("unexpected result: {:?}", res),
+            }
+            proxy.await.unwrap()?;
+            Ok(())
+        })
+    }
+}
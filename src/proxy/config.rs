@@ -0,0 +1,201 @@
+use std::env;
+
+#[cfg(feature = "tokio")]
+use tokio::net::TcpStream;
+
+#[cfg(feature = "tokio")]
+use super::{connect_directly, ProxyStream};
+use super::{NoProxy, Proxy, ProxyScheme};
+#[cfg(feature = "tokio")]
+use crate::IntoTargetAddr;
+use crate::{Result, TargetAddr};
+
+/// The variables naming the proxy, in order of precedence.
+const PROXY_VARS: [&str; 4] = ["all_proxy", "ALL_PROXY", "socks_proxy", "SOCKS_PROXY"];
+
+/// The variables listing the targets reached directly, in order of precedence.
+const NO_PROXY_VARS: [&str; 2] = ["no_proxy", "NO_PROXY"];
+
+/// Which targets are reached through which proxy.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "tokio")]
+/// # async fn connect() -> tokio_socks::Result<()> {
+/// use tokio_socks::proxy::ProxyConfig;
+///
+/// // ALL_PROXY=socks5h://proxy.example.com NO_PROXY=localhost,10.0.0.0/8
+/// let config = ProxyConfig::from_env()?;
+/// let stream = config.connect("example.com:80").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ProxyConfig {
+    proxy: Option<Proxy>,
+    no_proxy: NoProxy,
+}
+
+impl From<Proxy> for ProxyConfig {
+    fn from(proxy: Proxy) -> Self {
+        ProxyConfig::new(proxy)
+    }
+}
+
+impl ProxyConfig {
+    /// Creates a configuration reaching every target through `proxy`.
+    pub fn new<P: Into<Proxy>>(proxy: P) -> Self {
+        ProxyConfig {
+            proxy: Some(proxy.into()),
+            no_proxy: NoProxy::default(),
+        }
+    }
+
+    /// Creates a configuration reaching every target directly.
+    pub fn direct() -> Self {
+        ProxyConfig::default()
+    }
+
+    /// Reads the configuration from the environment.
+    ///
+    /// The proxy URL is read from the first variable set to a SOCKS URL among
+    /// `all_proxy`, `ALL_PROXY`, `socks_proxy` and `SOCKS_PROXY`, and the
+    /// targets reached directly from `no_proxy` or `NO_PROXY`. Variables set
+    /// to URLs of other schemes, such as the `http://` ones used by curl, are
+    /// skipped. Targets are reached directly if no proxy is set.
+    ///
+    /// # Error
+    ///
+    /// It fails if the SOCKS proxy URL is invalid.
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars<F>(var: F) -> Result<Self>
+    where F: Fn(&str) -> Option<String> {
+        let first_set = |names: &[&str], accepts: fn(&str) -> bool| {
+            names
+                .iter()
+                .filter_map(|name| var(name))
+                .map(|value| value.trim().to_owned())
+                .find(|value| !value.is_empty() && accepts(value))
+        };
+        let proxy = match first_set(&PROXY_VARS, is_socks_url) {
+            Some(url) => Some(url.parse()?),
+            None => None,
+        };
+        let no_proxy = first_set(&NO_PROXY_VARS, |_| true).map_or_else(NoProxy::default, |list| NoProxy::new(&list));
+        Ok(ProxyConfig { proxy, no_proxy })
+    }
+
+    /// Sets the targets reached directly.
+    pub fn no_proxy(mut self, no_proxy: NoProxy) -> Self {
+        self.no_proxy = no_proxy;
+        self
+    }
+
+    /// Returns the proxy to reach `target` through, or `None` if it is
+    /// reached directly.
+    pub fn proxy_for(&self, target: &TargetAddr<'_>) -> Option<&Proxy> {
+        match &self.proxy {
+            Some(_) if self.no_proxy.matches(target) => None,
+            proxy => proxy.as_ref(),
+        }
+    }
+
+    /// Connects to a target server, through the proxy unless the target is
+    /// reached directly.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`.
+    #[cfg(feature = "tokio")]
+    pub async fn connect<'t, T>(&self, target: T) -> Result<ProxyStream<TcpStream>>
+    where T: IntoTargetAddr<'t> {
+        let target = target.into_target_addr()?;
+        if let Some(proxy) = self.proxy_for(&target) {
+            return proxy.connect(target).await;
+        }
+        Ok(ProxyStream::Direct {
+            socket: connect_directly(&target).await?,
+            target: target.to_owned(),
+        })
+    }
+}
+
+/// Returns whether `url` has a SOCKS scheme, URLs without any scheme being
+/// HTTP ones for curl.
+fn is_socks_url(url: &str) -> bool {
+    url.split_once("://")
+        .is_some_and(|(scheme, _)| scheme.parse::<ProxyScheme>().is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{Error, IntoTargetAddr};
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<ProxyConfig> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        ProxyConfig::from_vars(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    fn is_proxied(config: &ProxyConfig, target: &str) -> Result<bool> {
+        Ok(config.proxy_for(&target.into_target_addr()?).is_some())
+    }
+
+    #[test]
+    fn variables_take_precedence_in_order() -> Result<()> {
+        let config = from_vars(&[
+            ("ALL_PROXY", "socks4a://proxy"),
+            ("all_proxy", " "),
+            ("SOCKS_PROXY", "socks5://proxy"),
+        ])?;
+        assert!(matches!(config.proxy, Some(Proxy::Socks4(_))));
+
+        let config = from_vars(&[("SOCKS_PROXY", "socks5h://proxy"), ("no_proxy", "localhost")])?;
+        assert!(matches!(config.proxy, Some(Proxy::Socks5(_))));
+        assert!(is_proxied(&config, "example.com:80")?);
+        assert!(!is_proxied(&config, "localhost:80")?);
+
+        let config = from_vars(&[("NO_PROXY", "localhost")])?;
+        assert!(!is_proxied(&config, "example.com:80")?);
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn excluded_targets_are_reached_directly() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            let config = ProxyConfig::new("socks5://127.0.0.1:9".parse::<Proxy>()?).no_proxy(NoProxy::new("127.0.0.1"));
+            let stream = config.connect(listener.local_addr()?).await?;
+            match stream {
+                ProxyStream::Direct { socket, .. } => assert_eq!(socket.peer_addr()?, listener.local_addr()?),
+                _ => panic!("expected a direct connection"),
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn invalid_proxy_url_should_fail() {
+        let res = from_vars(&[("ALL_PROXY", "socks5://proxy:port")]);
+        assert!(matches!(res, Err(Error::InvalidProxyUrl(_))));
+    }
+
+    #[test]
+    fn other_schemes_are_skipped() -> Result<()> {
+        let config = from_vars(&[("ALL_PROXY", "http://proxy:3128"), ("SOCKS_PROXY", "socks5h://proxy")])?;
+        assert!(matches!(config.proxy, Some(Proxy::Socks5(_))));
+
+        let config = from_vars(&[("all_proxy", "proxy:3128"), ("ALL_PROXY", "HTTPS://proxy")])?;
+        assert!(config.proxy.is_none());
+        Ok(())
+    }
+}
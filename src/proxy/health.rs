@@ -0,0 +1,227 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures_util::future;
+
+use super::{pool::is_proxy_failure, ProxyPool};
+use crate::{IntoTargetAddr, Result, TargetAddr};
+
+/// Probes the proxies of a `ProxyPool` in the background, so that dead
+/// proxies are skipped before user traffic goes through them.
+///
+/// A probe connects to the proxy and, for SOCKS5, negotiates the
+/// authentication method. When a probe target is set, it also connects to it
+/// through the proxy. The outcome is recorded in the pool like the one of a
+/// connection, and is available from `ProxyPool::status`: a proxy replying
+/// that the probe target can't be reached is alive, and its failure is left
+/// to the target.
+///
+/// # Examples
+///
+/// ```no_run
+/// # async fn run() -> tokio_socks::Result<()> {
+/// use std::{sync::Arc, time::Duration};
+///
+/// use tokio_socks::proxy::{HealthChecker, Proxy, ProxyPool};
+///
+/// let pool = Arc::new(ProxyPool::new(vec![
+///     "socks5://10.0.0.1".parse::<Proxy>()?,
+///     "socks5://10.0.0.2".parse::<Proxy>()?,
+/// ]));
+/// let checker = HealthChecker::new(pool.clone())
+///     .interval(Duration::from_secs(10))
+///     .probe_target("example.com:80")?;
+/// tokio::spawn(checker.run());
+/// let stream = pool.connect("example.com:80").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HealthChecker {
+    pool: Arc<ProxyPool>,
+    interval: Duration,
+    timeout: Duration,
+    target: Option<TargetAddr<'static>>,
+}
+
+impl HealthChecker {
+    /// Creates a checker of the proxies of `pool`, probing them every 30
+    /// seconds with a timeout of 10 seconds.
+    pub fn new(pool: Arc<ProxyPool>) -> Self {
+        HealthChecker {
+            pool,
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+            target: None,
+        }
+    }
+
+    /// Sets the delay between the end of a round of probes and the start of
+    /// the next one.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Bounds the time of each probe, on top of the timeouts of the proxy's
+    /// client.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the target connected to through each proxy when probing it.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`.
+    pub fn probe_target<'t, T>(mut self, target: T) -> Result<Self>
+    where T: IntoTargetAddr<'t> {
+        self.target = Some(target.into_target_addr()?.to_owned());
+        Ok(self)
+    }
+
+    /// Probes every proxy of the pool once, all at the same time.
+    pub async fn check(&self) {
+        let probes = self.pool.proxies().enumerate().map(|(index, proxy)| async move {
+            let started = Instant::now();
+            let res = match tokio::time::timeout(self.timeout, proxy.probe(self.target.clone())).await {
+                Ok(res) => res,
+                Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "health check timed out").into()),
+            };
+            match res {
+                Err(err) if is_proxy_failure(&err) => self.pool.record_failure(index, Instant::now(), &err),
+                _ => self.pool.record_success(index, started.elapsed()),
+            }
+        });
+        future::join_all(probes).await;
+    }
+
+    /// Probes the proxies of the pool forever, to be spawned as a task.
+    pub async fn run(self) {
+        loop {
+            self.check().await;
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::tcp::Socks5Client;
+
+    #[test]
+    fn check_records_status() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let alive = listener.local_addr()?;
+            let dead = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+            let proxy = tokio::spawn(async move {
+                let (mut socket, _) = listener.accept().await?;
+                let mut buf = [0; 3];
+                socket.read_exact(&mut buf).await?;
+                assert_eq!(buf, [0x05, 1, 0x00]);
+                socket.write_all(&[0x05, 0x00]).await?;
+                // No command follows the method negotiation.
+                assert_eq!(socket.read(&mut buf).await?, 0);
+                io::Result::Ok(())
+            });
+
+            let pool = Arc::new(ProxyPool::new(vec![
+                Socks5Client::builder(alive).build()?,
+                Socks5Client::builder(dead).build()?,
+            ]));
+            HealthChecker::new(pool.clone()).check().await;
+            proxy.await.unwrap()?;
+
+            let status = pool.status(0).unwrap();
+            assert!(status.is_healthy());
+            assert!(status.latency().is_some());
+            assert_eq!(status.last_error(), None);
+            let status = pool.status(1).unwrap();
+            assert!(!status.is_healthy());
+            assert_eq!(status.consecutive_failures(), 1);
+            assert!(status.last_error().is_some());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn proxies_are_probed_concurrently() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            // The proxies accept connections but never reply.
+            let mut listeners = Vec::new();
+            let mut proxies = Vec::new();
+            for _ in 0..3 {
+                let listener = TcpListener::bind("127.0.0.1:0").await?;
+                proxies.push(Socks5Client::builder(listener.local_addr()?).build()?);
+                listeners.push(listener);
+            }
+
+            let pool = Arc::new(ProxyPool::new(proxies));
+            let started = Instant::now();
+            HealthChecker::new(pool.clone())
+                .timeout(Duration::from_millis(200))
+                .check()
+                .await;
+            assert!(started.elapsed() < Duration::from_millis(500));
+            for index in 0..3 {
+                assert!(!pool.status(index).unwrap().is_healthy());
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn probe_target_is_connected_to() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let addr = listener.local_addr()?;
+            // The target is unreachable, then the proxy fails, then recovers.
+            let proxy = tokio::spawn(async move {
+                for reply in [0x04, 0x01, 0x00] {
+                    let (mut socket, _) = listener.accept().await?;
+                    let mut buf = [0; 10];
+                    socket.read_exact(&mut buf[..3]).await?;
+                    socket.write_all(&[0x05, 0x00]).await?;
+                    socket.read_exact(&mut buf).await?;
+                    assert_eq!(buf, [0x05, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0, 80]);
+                    socket.write_all(&[0x05, reply, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await?;
+                }
+                io::Result::Ok(())
+            });
+
+            let pool = Arc::new(ProxyPool::new(vec![Socks5Client::builder(addr).build()?]));
+            let checker = HealthChecker::new(pool.clone()).probe_target("10.0.0.1:80")?;
+            checker.check().await;
+            let status = pool.status(0).unwrap();
+            assert!(status.is_healthy());
+            assert_eq!(status.last_error(), None);
+
+            checker.check().await;
+            let status = pool.status(0).unwrap();
+            assert!(!status.is_healthy());
+            assert_eq!(status.last_error(), Some("General SOCKS server failure"));
+
+            checker.check().await;
+            proxy.await.unwrap()?;
+            let status = pool.status(0).unwrap();
+            assert!(status.is_healthy());
+            assert_eq!(status.last_error(), None);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,208 @@
+use std::net::IpAddr;
+
+use crate::TargetAddr;
+
+/// The targets reached directly instead of through the proxy, following the
+/// `NO_PROXY` conventions of curl.
+///
+/// The list is separated by commas or spaces. Each entry is one of:
+///
+/// - `*`, matching every target;
+/// - a domain name, matching itself and its subdomains, with or without a
+///   leading dot;
+/// - an IP address or a CIDR range, such as `10.0.0.0/8` or `fe80::/10`,
+///   matching the targets given by IP address.
+///
+/// Any entry except `*` may end with `:port` to only match that port, using
+/// brackets around IPv6 addresses. Invalid entries are ignored.
+///
+/// # Examples
+///
+/// ```
+/// use tokio_socks::{proxy::NoProxy, IntoTargetAddr};
+///
+/// let no_proxy = NoProxy::new("localhost, .internal.example.com, 10.0.0.0/8, [::1]:8080");
+/// assert!(no_proxy.matches(&"db.internal.example.com:5432".into_target_addr()?));
+/// assert!(no_proxy.matches(&"10.1.2.3:80".into_target_addr()?));
+/// assert!(!no_proxy.matches(&"[::1]:80".into_target_addr()?));
+/// assert!(!no_proxy.matches(&"example.com:80".into_target_addr()?));
+/// # Ok::<(), tokio_socks::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NoProxy {
+    all: bool,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    host: HostRule,
+    port: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostRule {
+    /// A lowercase domain name, without leading or trailing dots.
+    Domain(String),
+    /// An address range, given by its first address and prefix length.
+    Network(IpAddr, u8),
+}
+
+impl NoProxy {
+    /// Parses a `NO_PROXY` list.
+    pub fn new(list: &str) -> Self {
+        let mut no_proxy = NoProxy::default();
+        for entry in list.split(|c: char| c == ',' || c.is_whitespace()) {
+            match entry {
+                "" => {},
+                "*" => no_proxy.all = true,
+                entry => no_proxy.rules.extend(Rule::parse(entry)),
+            }
+        }
+        no_proxy
+    }
+
+    /// Returns whether `target` should be reached directly.
+    pub fn matches(&self, target: &TargetAddr<'_>) -> bool {
+        self.all || self.rules.iter().any(|rule| rule.matches(target))
+    }
+}
+
+impl Rule {
+    fn parse(entry: &str) -> Option<Self> {
+        let (host, port) = split_port(entry)?;
+        let host = match host.split_once('/') {
+            Some((ip, prefix)) => {
+                let ip: IpAddr = ip.parse().ok()?;
+                let prefix: u8 = prefix.parse().ok()?;
+                if prefix > max_prefix(ip) {
+                    return None;
+                }
+                HostRule::Network(ip, prefix)
+            },
+            None => match host.parse::<IpAddr>() {
+                Ok(ip) => HostRule::Network(ip, max_prefix(ip)),
+                Err(_) => {
+                    let domain = host.trim_start_matches('.').trim_end_matches('.');
+                    if domain.is_empty() {
+                        return None;
+                    }
+                    HostRule::Domain(domain.to_ascii_lowercase())
+                },
+            },
+        };
+        Some(Rule { host, port })
+    }
+
+    fn matches(&self, target: &TargetAddr<'_>) -> bool {
+        let (host_matches, port) = match (&self.host, target) {
+            (HostRule::Domain(domain), TargetAddr::Domain(host, port)) => {
+                let host = host.trim_end_matches('.');
+                let matches = host.len() >= domain.len() && {
+                    let (prefix, suffix) = host.split_at(host.len() - domain.len());
+                    suffix.eq_ignore_ascii_case(domain) && (prefix.is_empty() || prefix.ends_with('.'))
+                };
+                (matches, *port)
+            },
+            (HostRule::Network(network, prefix), TargetAddr::Ip(addr)) => {
+                (in_network(addr.ip(), *network, *prefix), addr.port())
+            },
+            _ => return false,
+        };
+        host_matches && self.port.is_none_or(|rule_port| rule_port == port)
+    }
+}
+
+/// Splits the optional port off a `NO_PROXY` entry, returning `None` if it is
+/// invalid.
+fn split_port(entry: &str) -> Option<(&str, Option<u16>)> {
+    if let Some(rest) = entry.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')?;
+        return match rest {
+            "" => Some((host, None)),
+            _ => Some((host, Some(rest.strip_prefix(':')?.parse().ok()?))),
+        };
+    }
+    match entry.split_once(':') {
+        // A single colon separates the port; more make an IPv6 address.
+        Some((host, port)) if !port.contains(':') => Some((host, Some(port.parse().ok()?))),
+        _ => Some((entry, None)),
+    }
+}
+
+pub(crate) fn max_prefix(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+pub(crate) fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let mask = |bits: u32| u128::MAX.checked_shl(bits - u32::from(prefix)).unwrap_or(0);
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = mask(32) as u32;
+            u32::from(ip) & mask == u32::from(network) & mask
+        },
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = mask(128);
+            u128::from(ip) & mask == u128::from(network) & mask
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntoTargetAddr, Result};
+
+    fn matches(list: &str, target: &str) -> Result<bool> {
+        Ok(NoProxy::new(list).matches(&target.into_target_addr()?))
+    }
+
+    #[test]
+    fn domains_match_their_subdomains() -> Result<()> {
+        assert!(matches("example.com", "example.com:80")?);
+        assert!(matches("example.com", "WWW.Example.COM.:80")?);
+        assert!(matches(".example.com", "www.example.com:80")?);
+        assert!(!matches("example.com", "badexample.com:80")?);
+        assert!(!matches("www.example.com", "example.com:80")?);
+        assert!(!matches("example.com", "93.184.216.34:80")?);
+        Ok(())
+    }
+
+    #[test]
+    fn networks_match_their_addresses() -> Result<()> {
+        assert!(matches("10.0.0.0/8", "10.255.0.1:80")?);
+        assert!(!matches("10.0.0.0/8", "11.0.0.1:80")?);
+        assert!(matches("192.168.1.1", "192.168.1.1:80")?);
+        assert!(!matches("192.168.1.1", "192.168.1.2:80")?);
+        assert!(matches("fe80::/10", "[fe80::1]:80")?);
+        assert!(matches("::1", "[::1]:80")?);
+        assert!(matches("0.0.0.0/0", "1.2.3.4:80")?);
+        assert!(!matches("0.0.0.0/0", "[::1]:80")?);
+        assert!(!matches("127.0.0.1", "localhost:80")?);
+        Ok(())
+    }
+
+    #[test]
+    fn ports_and_wildcard() -> Result<()> {
+        assert!(matches("example.com:443", "example.com:443")?);
+        assert!(!matches("example.com:443", "example.com:80")?);
+        assert!(matches("[::1]:8080", "[::1]:8080")?);
+        assert!(!matches("[::1]:8080", "[::1]:80")?);
+        assert!(matches("foo, *", "example.com:80")?);
+        assert!(matches("foo bar,,localhost", "localhost:80")?);
+        assert!(!matches("", "localhost:80")?);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_entries_are_ignored() {
+        assert_eq!(
+            NoProxy::new("10.0.0.0/33, example.com:port, [::1, ., 10.0.0.0/x"),
+            NoProxy::default()
+        );
+    }
+}
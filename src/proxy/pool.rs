@@ -0,0 +1,414 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+        MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::net::TcpStream;
+
+use super::{Proxy, ProxyStream};
+use crate::{Error, IntoTargetAddr, ProxyUnreachable, Result};
+
+/// How a `ProxyPool` picks the proxy to try first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Selection {
+    /// Each connection starts with the proxy following the one the previous
+    /// connection started with.
+    #[default]
+    RoundRobin,
+    /// Each connection starts with a random proxy.
+    Random,
+    /// Each connection starts with the proxy which failed the longest time
+    /// ago, or never failed.
+    LeastRecentFailure,
+}
+
+/// A set of equivalent proxies, connected to with failover.
+///
+/// A proxy is marked unhealthy when the connection to it or the handshake with
+/// it fails, and is skipped until its back-off delay expires. The delay
+/// doubles with each consecutive failure, and a success resets it. Errors
+/// about the target, such as `Error::HostUnreachable`, don't count as
+/// failures of the proxy.
+///
+/// # Examples
+///
+/// ```no_run
+/// # async fn connect() -> tokio_socks::Result<()> {
+/// use tokio_socks::{
+///     proxy::{ProxyPool, Selection},
+///     tcp::Socks5Client,
+/// };
+///
+/// let pool = ProxyPool::new(vec![
+///     Socks5Client::builder("10.0.0.1:1080").build()?,
+///     Socks5Client::builder("10.0.0.2:1080").build()?,
+/// ])
+/// .selection(Selection::LeastRecentFailure);
+/// let stream = pool.connect("example.com:80").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ProxyPool {
+    members: Vec<Member>,
+    selection: Selection,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    cursor: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Member {
+    proxy: Proxy,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    consecutive_failures: u32,
+    last_failure: Option<Instant>,
+    last_error: Option<String>,
+    retry_at: Option<Instant>,
+    latency: Option<Duration>,
+}
+
+/// A snapshot of the health of a proxy of a `ProxyPool`, as seen by the
+/// connections through it and by a `HealthChecker`.
+#[derive(Debug, Clone)]
+pub struct ProxyStatus {
+    healthy: bool,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+    latency: Option<Duration>,
+}
+
+impl ProxyStatus {
+    /// Returns whether the proxy is not backing off from a failure.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy
+    }
+
+    /// Returns the number of failures since the last success.
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// Returns the message of the error of the last failure, if the proxy
+    /// hasn't succeeded since.
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Returns the time the last successful connection or health check took.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+}
+
+impl ProxyPool {
+    /// Creates a pool of `proxies`, selected in round-robin order.
+    pub fn new<I, P>(proxies: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<Proxy>,
+    {
+        ProxyPool {
+            members: proxies
+                .into_iter()
+                .map(|proxy| Member {
+                    proxy: proxy.into(),
+                    health: Mutex::default(),
+                })
+                .collect(),
+            selection: Selection::RoundRobin,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            cursor: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets how the proxy to try first is picked.
+    pub fn selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Sets the delay a proxy is skipped for after its first failure, and the
+    /// maximum it doubles up to with the following ones. They default to one
+    /// second and one minute.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Returns the proxies of the pool.
+    pub fn proxies(&self) -> impl Iterator<Item = &Proxy> {
+        self.members.iter().map(|member| &member.proxy)
+    }
+
+    /// Returns whether the proxy at `index` is not backing off from a failure.
+    pub fn is_healthy(&self, index: usize) -> bool {
+        self.members
+            .get(index)
+            .is_some_and(|member| member.health().is_healthy(Instant::now()))
+    }
+
+    /// Returns the status of the proxy at `index`.
+    pub fn status(&self, index: usize) -> Option<ProxyStatus> {
+        let health = self.members.get(index)?.health();
+        Some(ProxyStatus {
+            healthy: health.is_healthy(Instant::now()),
+            consecutive_failures: health.consecutive_failures,
+            last_error: health.last_error.clone(),
+            latency: health.latency,
+        })
+    }
+
+    /// Connects to a target server through the first proxy of the pool that
+    /// succeeds.
+    ///
+    /// Healthy proxies are tried first, in the order of the selection, then
+    /// the unhealthy ones, starting with the one whose back-off expires first.
+    ///
+    /// # Error
+    ///
+    /// It fails with the error of the last proxy tried if every proxy fails,
+    /// or right away with an error about the target.
+    pub async fn connect<'t, T>(&self, target: T) -> Result<ProxyStream<TcpStream>>
+    where T: IntoTargetAddr<'t> {
+        let target = target.into_target_addr()?;
+        let mut last_err = None;
+        for index in self.candidates(Instant::now()) {
+            let started = Instant::now();
+            match self.members[index].proxy.connect(target.clone()).await {
+                // The proxy wasn't contacted.
+                Err(err @ Error::TargetResolutionFailure(_)) => return Err(err),
+                Err(err) if is_proxy_failure(&err) => {
+                    self.record_failure(index, Instant::now(), &err);
+                    last_err = Some(err);
+                },
+                res => {
+                    self.record_success(index, started.elapsed());
+                    return res;
+                },
+            }
+        }
+        Err(last_err.unwrap_or_else(|| Error::ProxyServerUnreachable(ProxyUnreachable::default())))
+    }
+
+    /// Returns the indices of the members in the order they are tried.
+    fn candidates(&self, now: Instant) -> Vec<usize> {
+        let len = self.members.len();
+        let start = match self.selection {
+            Selection::RoundRobin => self.cursor.fetch_add(1, Ordering::Relaxed),
+            Selection::Random => RandomState::new().build_hasher().finish() as usize,
+            Selection::LeastRecentFailure => 0,
+        };
+
+        let mut healthy = Vec::with_capacity(len);
+        let mut unhealthy = Vec::new();
+        for index in (0..len).map(|i| (start.wrapping_add(i)) % len) {
+            let health = self.members[index].health();
+            if health.is_healthy(now) {
+                healthy.push((health.last_failure, index));
+            } else {
+                unhealthy.push((health.retry_at, index));
+            }
+        }
+        if self.selection == Selection::LeastRecentFailure {
+            healthy.sort_by_key(|&(last_failure, _)| last_failure);
+        }
+        unhealthy.sort_by_key(|&(retry_at, _)| retry_at);
+        healthy
+            .into_iter()
+            .map(|(_, index)| index)
+            .chain(unhealthy.into_iter().map(|(_, index)| index))
+            .collect()
+    }
+
+    /// Marks the proxy at `index` as failed at `now` with `err`, backing off
+    /// from it.
+    pub(crate) fn record_failure(&self, index: usize, now: Instant, err: &Error) {
+        let mut health = self.members[index].health();
+        health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+        let factor = 1u32.checked_shl(health.consecutive_failures - 1).unwrap_or(u32::MAX);
+        let delay = self
+            .initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff));
+        health.last_failure = Some(now);
+        health.last_error = Some(err.to_string());
+        health.retry_at = Some(now + delay);
+    }
+
+    /// Marks the proxy at `index` as healthy, having taken `latency` to
+    /// connect through.
+    pub(crate) fn record_success(&self, index: usize, latency: Duration) {
+        let mut health = self.members[index].health();
+        health.consecutive_failures = 0;
+        health.last_error = None;
+        health.retry_at = None;
+        health.latency = Some(latency);
+    }
+}
+
+impl Member {
+    fn health(&self) -> MutexGuard<'_, Health> {
+        self.health.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Health {
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.retry_at.is_none_or(|retry_at| retry_at <= now)
+    }
+}
+
+/// Returns whether `err` is a failure of the proxy, rather than a rejection of
+/// the target which any other proxy would report as well.
+pub(super) fn is_proxy_failure(err: &Error) -> bool {
+    !matches!(
+        err,
+        Error::InvalidTargetAddress(_)
+            | Error::TargetResolutionFailure(_)
+            | Error::ConnectionNotAllowedByRuleset
+            | Error::NetworkUnreachable
+            | Error::HostUnreachable
+            | Error::ConnectionRefused
+            | Error::TtlExpired
+            | Error::AddressTypeNotSupported
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp::Socks5Client;
+
+    fn pool(len: u16, selection: Selection) -> Result<ProxyPool> {
+        let proxies = (0..len)
+            .map(|port| Socks5Client::builder(("127.0.0.1", port)).build())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ProxyPool::new(proxies).selection(selection))
+    }
+
+    #[test]
+    fn unhealthy_proxies_are_tried_last() -> Result<()> {
+        let now = Instant::now();
+        let pool = pool(3, Selection::RoundRobin)?;
+        assert_eq!(pool.candidates(now), [0, 1, 2]);
+        assert_eq!(pool.candidates(now), [1, 2, 0]);
+
+        pool.record_failure(0, now, &Error::GeneralSocksServerFailure);
+        pool.record_failure(2, now, &Error::GeneralSocksServerFailure);
+        pool.record_failure(2, now, &Error::GeneralSocksServerFailure);
+        assert!(!pool.is_healthy(0));
+        assert_eq!(pool.candidates(now), [1, 0, 2]);
+        assert_eq!(pool.candidates(now + Duration::from_millis(1500)), [0, 1, 2]);
+        assert_eq!(pool.candidates(now + Duration::from_millis(2500)), [1, 2, 0]);
+
+        pool.record_success(0, Duration::from_millis(10));
+        assert!(pool.is_healthy(0));
+        Ok(())
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() -> Result<()> {
+        let now = Instant::now();
+        let pool = pool(1, Selection::RoundRobin)?.backoff(Duration::from_secs(1), Duration::from_secs(5));
+        for expected in [1, 2, 4, 5, 5] {
+            pool.record_failure(0, now, &Error::GeneralSocksServerFailure);
+            let retry_at = pool.members[0].health().retry_at;
+            assert_eq!(retry_at, Some(now + Duration::from_secs(expected)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn least_recent_failure_first() -> Result<()> {
+        let now = Instant::now();
+        let pool = pool(3, Selection::LeastRecentFailure)?;
+        pool.record_failure(0, now, &Error::GeneralSocksServerFailure);
+        pool.record_failure(1, now + Duration::from_secs(1), &Error::GeneralSocksServerFailure);
+        let later = now + Duration::from_secs(10);
+        assert_eq!(pool.candidates(later), [2, 0, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn connect_fails_over() -> Result<()> {
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let dead = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let alive = listener.local_addr()?;
+            let proxy = tokio::spawn(async move {
+                let (mut socket, _) = listener.accept().await?;
+                let mut buf = [0; 10];
+                socket.read_exact(&mut buf[..3]).await?;
+                socket.write_all(&[0x05, 0x00]).await?;
+                socket.read_exact(&mut buf).await?;
+                socket.write_all(&[0x05, 0x04, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await
+            });
+
+            let pool = ProxyPool::new(vec![
+                Socks5Client::builder(dead).build()?,
+                Socks5Client::builder(alive).build()?,
+            ]);
+            let res = pool.connect("127.0.0.1:80").await;
+            assert!(matches!(res, Err(Error::HostUnreachable)));
+            let status = pool.status(0).unwrap();
+            assert!(!status.is_healthy());
+            assert!(status.last_error().is_some());
+            let status = pool.status(1).unwrap();
+            assert!(status.is_healthy());
+            assert!(status.latency().is_some());
+            proxy.await.unwrap()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn target_resolution_failures_are_not_proxy_failures() -> Result<()> {
+        use futures_util::future;
+
+        use crate::resolve::{ResolveFuture, Resolver, TargetResolution};
+
+        struct FailingResolver;
+
+        impl Resolver for FailingResolver {
+            fn resolve(&self, _: &str, _: u16) -> ResolveFuture {
+                Box::pin(future::ready(Err(std::io::ErrorKind::NotFound.into())))
+            }
+        }
+
+        let proxies = (0..2)
+            .map(|port| {
+                Socks5Client::builder(("127.0.0.1", port))
+                    .resolver(FailingResolver)
+                    .target_resolution(TargetResolution::Local)
+                    .build()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let pool = ProxyPool::new(proxies);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let res = runtime.block_on(pool.connect("mistyped.example:80"));
+        assert!(matches!(res, Err(Error::TargetResolutionFailure(_))));
+        assert!(pool.is_healthy(0));
+        assert!(pool.is_healthy(1));
+        assert_eq!(pool.status(0).unwrap().latency(), None);
+        Ok(())
+    }
+}
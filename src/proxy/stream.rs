@@ -0,0 +1,183 @@
+use std::ops::{Deref, DerefMut};
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    io::AsyncSocket,
+    tcp::{Socks4Stream, Socks5Stream},
+    TargetAddr,
+};
+
+/// A connection to a target server, either direct or through a proxy.
+///
+/// For convenience, it can be dereferenced to its inner socket.
+#[derive(Debug)]
+pub enum ProxyStream<S> {
+    /// A direct connection to the target.
+    Direct {
+        /// The socket connected to the target.
+        socket: S,
+        /// The address of the target.
+        target: TargetAddr<'static>,
+    },
+    /// A connection through a SOCKS4 proxy.
+    Socks4 {
+        /// The stream connected to the proxy.
+        stream: Socks4Stream<S>,
+        /// The address of the target.
+        target: TargetAddr<'static>,
+    },
+    /// A connection through a SOCKS5 proxy.
+    Socks5 {
+        /// The stream connected to the proxy.
+        stream: Socks5Stream<S>,
+        /// The address of the target.
+        target: TargetAddr<'static>,
+    },
+}
+
+impl<S> ProxyStream<S>
+where S: AsyncSocket + Unpin
+{
+    /// Returns the address of the target server, as it was requested.
+    pub fn target_addr(&self) -> TargetAddr<'_> {
+        let target = match self {
+            ProxyStream::Direct { target, .. }
+            | ProxyStream::Socks4 { target, .. }
+            | ProxyStream::Socks5 { target, .. } => target,
+        };
+        match target {
+            TargetAddr::Ip(addr) => TargetAddr::Ip(*addr),
+            TargetAddr::Domain(domain, port) => TargetAddr::Domain((&**domain).into(), *port),
+        }
+    }
+
+    /// Returns the address the proxy bound to connect to the target, or
+    /// `None` for a direct connection.
+    pub fn bound_addr(&self) -> Option<TargetAddr<'_>> {
+        match self {
+            ProxyStream::Direct { .. } => None,
+            ProxyStream::Socks4 { stream, .. } => Some(stream.target_addr()),
+            ProxyStream::Socks5 { stream, .. } => Some(stream.target_addr()),
+        }
+    }
+
+    /// Consumes the `ProxyStream`, returning the inner socket.
+    pub fn into_inner(self) -> S {
+        match self {
+            ProxyStream::Direct { socket, .. } => socket,
+            ProxyStream::Socks4 { stream, .. } => stream.into_inner(),
+            ProxyStream::Socks5 { stream, .. } => stream.into_inner(),
+        }
+    }
+}
+
+impl<S> Deref for ProxyStream<S> {
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ProxyStream::Direct { socket, .. } => socket,
+            ProxyStream::Socks4 { stream, .. } => stream,
+            ProxyStream::Socks5 { stream, .. } => stream,
+        }
+    }
+}
+
+impl<S> DerefMut for ProxyStream<S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            ProxyStream::Direct { socket, .. } => socket,
+            ProxyStream::Socks4 { stream, .. } => stream,
+            ProxyStream::Socks5 { stream, .. } => stream,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S> tokio::io::AsyncRead for ProxyStream<S>
+where S: tokio::io::AsyncRead + Unpin
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut tokio::io::ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxyStream::Direct { socket, .. } => tokio::io::AsyncRead::poll_read(Pin::new(socket), cx, buf),
+            ProxyStream::Socks4 { stream, .. } => tokio::io::AsyncRead::poll_read(Pin::new(stream), cx, buf),
+            ProxyStream::Socks5 { stream, .. } => tokio::io::AsyncRead::poll_read(Pin::new(stream), cx, buf),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S> tokio::io::AsyncWrite for ProxyStream<S>
+where S: tokio::io::AsyncWrite + Unpin
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ProxyStream::Direct { socket, .. } => tokio::io::AsyncWrite::poll_write(Pin::new(socket), cx, buf),
+            ProxyStream::Socks4 { stream, .. } => tokio::io::AsyncWrite::poll_write(Pin::new(stream), cx, buf),
+            ProxyStream::Socks5 { stream, .. } => tokio::io::AsyncWrite::poll_write(Pin::new(stream), cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxyStream::Direct { socket, .. } => tokio::io::AsyncWrite::poll_flush(Pin::new(socket), cx),
+            ProxyStream::Socks4 { stream, .. } => tokio::io::AsyncWrite::poll_flush(Pin::new(stream), cx),
+            ProxyStream::Socks5 { stream, .. } => tokio::io::AsyncWrite::poll_flush(Pin::new(stream), cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxyStream::Direct { socket, .. } => tokio::io::AsyncWrite::poll_shutdown(Pin::new(socket), cx),
+            ProxyStream::Socks4 { stream, .. } => tokio::io::AsyncWrite::poll_shutdown(Pin::new(stream), cx),
+            ProxyStream::Socks5 { stream, .. } => tokio::io::AsyncWrite::poll_shutdown(Pin::new(stream), cx),
+        }
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<S> futures_io::AsyncRead for ProxyStream<S>
+where S: futures_io::AsyncRead + Unpin
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ProxyStream::Direct { socket, .. } => futures_io::AsyncRead::poll_read(Pin::new(socket), cx, buf),
+            ProxyStream::Socks4 { stream, .. } => futures_io::AsyncRead::poll_read(Pin::new(stream), cx, buf),
+            ProxyStream::Socks5 { stream, .. } => futures_io::AsyncRead::poll_read(Pin::new(stream), cx, buf),
+        }
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<S> futures_io::AsyncWrite for ProxyStream<S>
+where S: futures_io::AsyncWrite + Unpin
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ProxyStream::Direct { socket, .. } => futures_io::AsyncWrite::poll_write(Pin::new(socket), cx, buf),
+            ProxyStream::Socks4 { stream, .. } => futures_io::AsyncWrite::poll_write(Pin::new(stream), cx, buf),
+            ProxyStream::Socks5 { stream, .. } => futures_io::AsyncWrite::poll_write(Pin::new(stream), cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxyStream::Direct { socket, .. } => futures_io::AsyncWrite::poll_flush(Pin::new(socket), cx),
+            ProxyStream::Socks4 { stream, .. } => futures_io::AsyncWrite::poll_flush(Pin::new(stream), cx),
+            ProxyStream::Socks5 { stream, .. } => futures_io::AsyncWrite::poll_flush(Pin::new(stream), cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxyStream::Direct { socket, .. } => futures_io::AsyncWrite::poll_close(Pin::new(socket), cx),
+            ProxyStream::Socks4 { stream, .. } => futures_io::AsyncWrite::poll_close(Pin::new(stream), cx),
+            ProxyStream::Socks5 { stream, .. } => futures_io::AsyncWrite::poll_close(Pin::new(stream), cx),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! Name resolution of proxy server addresses.
+//!
+//! Proxies given as a host name, such as `"proxy.example.com:1080"`, are
+//! resolved by the default resolver: `TokioResolver` with the `tokio` feature,
+//! `BlockingResolver` otherwise. `ResolveWith` resolves them with any other
+//! `Resolver` instead.
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    pin::Pin,
+    sync::Arc,
+};
+
+use futures_util::future;
+
+use crate::{Error, ProxyAddrsStream, Result, TargetAddr, ToProxyAddrs};
+
+/// The future returned by `Resolver::resolve`.
+pub type ResolveFuture = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>>;
+
+/// An asynchronous DNS resolver.
+pub trait Resolver: Send + Sync {
+    /// Resolves `host` to the socket addresses of the proxy listening on
+    /// `port`.
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture;
+}
+
+impl<R: Resolver + ?Sized> Resolver for Arc<R> {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        (**self).resolve(host, port)
+    }
+}
+
+impl<R: Resolver + ?Sized> Resolver for &R {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        (**self).resolve(host, port)
+    }
+}
+
+/// Resolves names with `tokio::net::lookup_host`, on tokio's blocking thread
+/// pool.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioResolver;
+
+#[cfg(feature = "tokio")]
+impl Resolver for TokioResolver {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        let host = host.to_owned();
+        Box::pin(async move { Ok(tokio::net::lookup_host((host.as_str(), port)).await?.collect()) })
+    }
+}
+
+/// Resolves names with `std::net::ToSocketAddrs`, blocking the calling thread.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BlockingResolver;
+
+impl Resolver for BlockingResolver {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        Box::pin(future::ready((host, port).to_socket_addrs().map(Iterator::collect)))
+    }
+}
+
+/// Which addresses of a proxy server are connected to, and in which order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressFamily {
+    /// Every address, starting with the family of the first one resolved.
+    #[default]
+    Any,
+    /// Every address, starting with IPv4.
+    PreferIpv4,
+    /// Every address, starting with IPv6.
+    PreferIpv6,
+    /// IPv4 addresses only.
+    Ipv4Only,
+    /// IPv6 addresses only.
+    Ipv6Only,
+}
+
+impl AddressFamily {
+    /// Filters and reorders the resolved `addrs` following this preference.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn arrange(self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        match self {
+            AddressFamily::Any => {},
+            AddressFamily::PreferIpv4 => addrs.sort_by_key(SocketAddr::is_ipv6),
+            AddressFamily::PreferIpv6 => addrs.sort_by_key(SocketAddr::is_ipv4),
+            AddressFamily::Ipv4Only => addrs.retain(SocketAddr::is_ipv4),
+            AddressFamily::Ipv6Only => addrs.retain(SocketAddr::is_ipv6),
+        }
+        addrs
+    }
+}
+
+/// Where the domain names of target servers are resolved.
+///
+/// Local resolution is useful with proxies which don't accept domain names,
+/// such as SOCKS4 proxies without the 4A extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TargetResolution {
+    /// Domain names are sent to the proxy, which resolves them.
+    #[default]
+    Remote,
+    /// Domain names are resolved locally, and the first address is sent to the
+    /// proxy.
+    Local,
+    /// Domain names are resolved locally, and the first IPv4 address is sent
+    /// to the proxy.
+    LocalIpv4,
+    /// Domain names are resolved locally like `Local`, but sent to the proxy
+    /// if the resolution fails.
+    LocalWithRemoteFallback,
+}
+
+impl TargetResolution {
+    /// Resolves `target` with `resolver` if this policy asks for it.
+    ///
+    /// Only IPv4 addresses are kept if `ipv4_only`, as required by SOCKS4.
+    pub(crate) async fn apply<'t, R: Resolver + ?Sized>(
+        self,
+        resolver: &R,
+        target: TargetAddr<'t>,
+        ipv4_only: bool,
+    ) -> Result<TargetAddr<'t>> {
+        let (host, port) = match (self, &target) {
+            (TargetResolution::Remote, _) | (_, TargetAddr::Ip(_)) => return Ok(target),
+            (_, TargetAddr::Domain(host, port)) => (host, *port),
+        };
+        let ipv4_only = ipv4_only || self == TargetResolution::LocalIpv4;
+        let resolved = match resolver.resolve(host, port).await {
+            Ok(addrs) => first_addr(addrs, ipv4_only),
+            Err(err) => Err(Error::TargetResolutionFailure(err)),
+        };
+        match resolved {
+            Err(_) if self == TargetResolution::LocalWithRemoteFallback => Ok(target),
+            resolved => resolved.map(TargetAddr::Ip),
+        }
+    }
+}
+
+fn first_addr(addrs: Vec<SocketAddr>, ipv4_only: bool) -> Result<SocketAddr> {
+    if addrs.is_empty() {
+        return Err(Error::TargetResolutionFailure(io::Error::new(
+            io::ErrorKind::NotFound,
+            "target resolved to no address",
+        )));
+    }
+    addrs
+        .into_iter()
+        .find(|addr| addr.is_ipv4() || !ipv4_only)
+        .ok_or(Error::AddressTypeNotSupported)
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) type DefaultResolver = TokioResolver;
+#[cfg(not(feature = "tokio"))]
+pub(crate) type DefaultResolver = BlockingResolver;
+
+/// A proxy server given by host name, resolved with a custom `Resolver`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "tokio")]
+/// # async fn connect() -> tokio_socks::Result<()> {
+/// use tokio_socks::{resolve::ResolveWith, tcp::Socks5Stream};
+/// # let resolver = tokio_socks::resolve::TokioResolver;
+///
+/// let proxy = ResolveWith::new("proxy.example.com", 1080, resolver);
+/// let stream = Socks5Stream::connect(proxy, "example.com:80").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ResolveWith<R> {
+    host: String,
+    port: u16,
+    resolver: R,
+}
+
+impl<R> fmt::Debug for ResolveWith<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResolveWith")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: Resolver> ResolveWith<R> {
+    /// Creates a proxy address resolving `host` with `resolver`.
+    pub fn new<H: Into<String>>(host: H, port: u16, resolver: R) -> Self {
+        ResolveWith {
+            host: host.into(),
+            port,
+            resolver,
+        }
+    }
+}
+
+impl<R: Resolver> ToProxyAddrs for ResolveWith<R> {
+    type Output = ProxyAddrsStream;
+
+    fn to_proxy_addrs(&self) -> Self::Output {
+        resolve_host(&self.resolver, &self.host, self.port)
+    }
+}
+
+/// Resolves `host` with `resolver`, unless it is an IP address already.
+pub(crate) fn resolve_host<R: Resolver + ?Sized>(resolver: &R, host: &str, port: u16) -> ProxyAddrsStream {
+    match host.parse() {
+        Ok(ip) => ProxyAddrsStream::ready(Ok(vec![SocketAddr::new(ip, port)])),
+        Err(_) => ProxyAddrsStream::resolving(resolver.resolve(host, port)),
+    }
+}
+
+/// Splits a `host:port` string and resolves it with `resolver`.
+pub(crate) fn resolve_host_port<R: Resolver + ?Sized>(resolver: &R, addr: &str) -> ProxyAddrsStream {
+    if let Ok(addr) = addr.parse() {
+        return ProxyAddrsStream::ready(Ok(vec![addr]));
+    }
+    let (host, port) = match addr.rsplit_once(':') {
+        Some(host_port) => host_port,
+        None => return ProxyAddrsStream::ready(Err(invalid_input("invalid socket address"))),
+    };
+    match port.parse() {
+        Ok(port) => resolve_host(resolver, host, port),
+        Err(_) => ProxyAddrsStream::ready(Err(invalid_input("invalid port value"))),
+    }
+}
+
+fn invalid_input(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use futures_executor::block_on;
+    use futures_util::StreamExt;
+
+    use super::*;
+
+    /// Resolves every name to localhost, recording the queries.
+    #[derive(Default)]
+    struct FakeResolver {
+        queries: Mutex<Vec<(String, u16)>>,
+    }
+
+    impl Resolver for FakeResolver {
+        fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+            self.queries.lock().unwrap().push((host.to_owned(), port));
+            Box::pin(future::ready(Ok(vec![SocketAddr::from(([127, 0, 0, 1], port))])))
+        }
+    }
+
+    /// Resolves every name to the same addresses, failing if there are none.
+    struct StaticResolver(Vec<SocketAddr>);
+
+    impl Resolver for StaticResolver {
+        fn resolve(&self, _: &str, _: u16) -> ResolveFuture {
+            let res = match self.0.as_slice() {
+                [] => Err(io::ErrorKind::NotFound.into()),
+                addrs => Ok(addrs.to_vec()),
+            };
+            Box::pin(future::ready(res))
+        }
+    }
+
+    fn collect(stream: ProxyAddrsStream) -> Result<Vec<SocketAddr>> {
+        block_on(stream.collect::<Vec<_>>()).into_iter().collect()
+    }
+
+    #[test]
+    fn resolves_host_names_only() -> Result<()> {
+        let resolver = FakeResolver::default();
+        let addrs = collect(resolve_host_port(&resolver, "proxy.example.com:1080"))?;
+        assert_eq!(addrs, [SocketAddr::from(([127, 0, 0, 1], 1080))]);
+        let addrs = collect(resolve_host_port(&resolver, "[::1]:1080"))?;
+        assert_eq!(addrs, [SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 1080))]);
+        let addrs = collect(ResolveWith::new("10.0.0.1", 1080, &resolver).to_proxy_addrs())?;
+        assert_eq!(addrs, [SocketAddr::from(([10, 0, 0, 1], 1080))]);
+        assert_eq!(*resolver.queries.lock().unwrap(), [(
+            "proxy.example.com".to_owned(),
+            1080
+        )]);
+        Ok(())
+    }
+
+    #[test]
+    fn target_resolution_policies() -> Result<()> {
+        let v4 = SocketAddr::from(([10, 0, 0, 1], 80));
+        let v6 = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 80));
+        let resolve = |resolution: TargetResolution, addrs: Vec<SocketAddr>, ipv4_only| {
+            let resolver = StaticResolver(addrs);
+            block_on(resolution.apply(&resolver, TargetAddr::Domain("example.com".into(), 80), ipv4_only))
+        };
+
+        assert_eq!(
+            resolve(TargetResolution::Remote, vec![v4], false)?.to_string(),
+            "example.com:80"
+        );
+        assert_eq!(
+            resolve(TargetResolution::Local, vec![v6, v4], false)?,
+            TargetAddr::Ip(v6)
+        );
+        assert_eq!(
+            resolve(TargetResolution::Local, vec![v6, v4], true)?,
+            TargetAddr::Ip(v4)
+        );
+        assert_eq!(
+            resolve(TargetResolution::LocalIpv4, vec![v6, v4], false)?,
+            TargetAddr::Ip(v4)
+        );
+        assert!(matches!(
+            resolve(TargetResolution::LocalIpv4, vec![v6], false),
+            Err(Error::AddressTypeNotSupported)
+        ));
+        assert!(matches!(
+            resolve(TargetResolution::Local, vec![], false),
+            Err(Error::TargetResolutionFailure(_))
+        ));
+        assert_eq!(
+            resolve(TargetResolution::LocalWithRemoteFallback, vec![v6, v4], false)?,
+            TargetAddr::Ip(v6)
+        );
+        assert_eq!(
+            resolve(TargetResolution::LocalWithRemoteFallback, vec![], false)?.to_string(),
+            "example.com:80"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_host_port_should_fail() {
+        let resolver = FakeResolver::default();
+        assert!(collect(resolve_host_port(&resolver, "proxy.example.com")).is_err());
+        assert!(collect(resolve_host_port(&resolver, "proxy.example.com:65536")).is_err());
+        assert!(resolver.queries.lock().unwrap().is_empty());
+    }
+}
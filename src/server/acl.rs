@@ -0,0 +1,623 @@
+#[cfg(any(feature = "toml", feature = "yaml"))]
+use std::{fs, path::Path};
+use std::{net::IpAddr, ops::RangeInclusive};
+
+#[cfg(any(feature = "toml", feature = "yaml"))]
+use serde::Deserialize;
+
+use super::Identity;
+use crate::{
+    proxy::{in_network, max_prefix},
+    tcp::socks5::wire::Command,
+    Error,
+    Result,
+    TargetAddr,
+};
+
+/// What an access control list does with a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(any(feature = "toml", feature = "yaml"), derive(Deserialize))]
+#[cfg_attr(any(feature = "toml", feature = "yaml"), serde(rename_all = "lowercase"))]
+pub enum Action {
+    /// The request is carried out.
+    Allow,
+    /// The request is denied, replying "connection not allowed by ruleset".
+    #[default]
+    Deny,
+}
+
+/// An ordered list of rules allowing or denying the requests of server
+/// clients.
+///
+/// The first rule matching a request decides its fate, and requests matching
+/// no rule get the default action, denying them unless set otherwise. Denied
+/// requests are answered with reply `0x02`, "connection not allowed by
+/// ruleset", by the `authorize` methods of the server requests.
+///
+/// With the `toml` or `yaml` feature, the list can be read from a file:
+///
+/// ```toml
+/// default = "deny"
+///
+/// [[rules]]
+/// action = "deny"
+/// targets = ["10.0.0.0/8", "*.internal.example.com"]
+///
+/// [[rules]]
+/// action = "allow"
+/// clients = ["192.168.0.0/16"]
+/// users = ["alice"]
+/// commands = ["connect"]
+/// ports = [80, "443", "8000-8999"]
+/// ```
+///
+/// Each field of a rule but `action` is optional and matches everything
+/// when left out. See `Rule` for the syntax of each entry.
+///
+/// # Examples
+///
+/// ```
+/// use tokio_socks::{
+///     server::{Acl, Action, Rule},
+///     tcp::socks5::wire::Command,
+///     IntoTargetAddr,
+/// };
+///
+/// let acl = Acl::new(Action::Deny)
+///     .rule(Rule::deny().target("10.0.0.0/8")?)
+///     .rule(Rule::allow().command(Command::Connect).ports(80..=443));
+/// let client = "192.168.1.2".parse().unwrap();
+/// let target = "example.com:443".into_target_addr()?;
+/// assert_eq!(
+///     acl.evaluate(client, None, Command::Connect, &target),
+///     Action::Allow
+/// );
+/// let target = "10.0.0.1:443".into_target_addr()?;
+/// assert_eq!(
+///     acl.evaluate(client, None, Command::Connect, &target),
+///     Action::Deny
+/// );
+/// # Ok::<(), tokio_socks::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    rules: Vec<Rule>,
+    default: Action,
+}
+
+impl Acl {
+    /// Creates a list without rules, applying `default` to every request.
+    pub fn new(default: Action) -> Self {
+        Acl {
+            rules: Vec::new(),
+            default,
+        }
+    }
+
+    /// Appends `rule`, which only applies to the requests no previous rule
+    /// matches.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Returns the action of the first rule matching the request of a client
+    /// connected from `client`, or the default action.
+    pub fn evaluate(
+        &self,
+        client: IpAddr,
+        identity: Option<&Identity>,
+        command: Command,
+        target: &TargetAddr<'_>,
+    ) -> Action {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(client, identity, command, target))
+            .map_or(self.default, |rule| rule.action)
+    }
+
+    /// Parses a list in TOML.
+    ///
+    /// # Error
+    ///
+    /// It fails with `Error::InvalidAcl` if the list is malformed.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> Result<Self> {
+        let config: Config = toml::from_str(s).map_err(|err| Error::InvalidAcl(err.to_string()))?;
+        config.into_acl()
+    }
+
+    /// Parses a list in YAML.
+    ///
+    /// # Error
+    ///
+    /// It fails with `Error::InvalidAcl` if the list is malformed.
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(s: &str) -> Result<Self> {
+        let config: Config = serde_yaml::from_str(s).map_err(|err| Error::InvalidAcl(err.to_string()))?;
+        config.into_acl()
+    }
+
+    /// Reads a list from the file at `path`, in YAML if its extension is
+    /// `yaml` or `yml`, in TOML otherwise.
+    ///
+    /// # Error
+    ///
+    /// It fails if the file can't be read, if the list is malformed, or if
+    /// the feature of its format is disabled.
+    #[cfg(any(feature = "toml", feature = "yaml"))]
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            #[cfg(feature = "yaml")]
+            Some("yaml") | Some("yml") => Self::from_yaml(&s),
+            #[cfg(feature = "toml")]
+            _ => Self::from_toml(&s),
+            #[cfg(not(feature = "toml"))]
+            _ => Err(Error::InvalidAcl("TOML support is disabled".to_owned())),
+        }
+    }
+}
+
+/// A rule of an `Acl`, matching the requests that fit all of its criteria.
+///
+/// Each criterion is a list matching a request if any of its entries does,
+/// or if it is empty.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    action: Action,
+    clients: Vec<(IpAddr, u8)>,
+    users: Vec<String>,
+    commands: Vec<Command>,
+    targets: Vec<TargetPattern>,
+    ports: Vec<RangeInclusive<u16>>,
+}
+
+#[derive(Debug, Clone)]
+enum TargetPattern {
+    Network(IpAddr, u8),
+    /// A lowercase domain name.
+    Domain(String),
+    /// A lowercase domain name, matching itself and its subdomains.
+    Suffix(String),
+    /// A lowercase pattern where `*` matches any sequence of characters and
+    /// `?` a single one.
+    Glob(String),
+}
+
+impl Rule {
+    fn new(action: Action) -> Self {
+        Rule {
+            action,
+            clients: Vec::new(),
+            users: Vec::new(),
+            commands: Vec::new(),
+            targets: Vec::new(),
+            ports: Vec::new(),
+        }
+    }
+
+    /// Creates a rule allowing every request.
+    pub fn allow() -> Self {
+        Self::new(Action::Allow)
+    }
+
+    /// Creates a rule denying every request.
+    pub fn deny() -> Self {
+        Self::new(Action::Deny)
+    }
+
+    /// Restricts the rule to clients connected from `network`, an IP address
+    /// or a CIDR range such as `10.0.0.0/8`.
+    ///
+    /// # Error
+    ///
+    /// It fails with `Error::InvalidAcl` if `network` is malformed.
+    pub fn client(mut self, network: &str) -> Result<Self> {
+        let network = parse_network(network).ok_or_else(|| invalid("client", network))?;
+        self.clients.push(network);
+        Ok(self)
+    }
+
+    /// Restricts the rule to clients authenticated as `name`.
+    pub fn user<N: Into<String>>(mut self, name: N) -> Self {
+        self.users.push(name.into());
+        self
+    }
+
+    /// Restricts the rule to `command`, SOCKS4 commands being matched by
+    /// their SOCKS5 counterpart.
+    pub fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    /// Restricts the rule to targets matching `pattern`, which is one of:
+    ///
+    /// - an IP address or a CIDR range, matching the targets given by IP
+    ///   address, even when sent as a domain name, IPv4-mapped IPv6
+    ///   addresses included;
+    /// - a domain name starting with a dot, such as `.example.com`, matching
+    ///   itself and its subdomains;
+    /// - a domain name with `*` or `?` wildcards, such as `*.example.com`, `*`
+    ///   matching any sequence of characters and `?` a single one;
+    /// - any other domain name, matching itself.
+    ///
+    /// Domain names are compared without case, and never match targets given
+    /// by IP address.
+    ///
+    /// # Error
+    ///
+    /// It fails with `Error::InvalidAcl` if `pattern` is empty.
+    pub fn target(mut self, pattern: &str) -> Result<Self> {
+        let domain = pattern.trim_end_matches('.').to_ascii_lowercase();
+        let pattern = match parse_network(pattern) {
+            Some((network, prefix)) => TargetPattern::Network(network, prefix),
+            None if domain.trim_start_matches('.').is_empty() => return Err(invalid("target", pattern)),
+            None if domain.contains(['*', '?']) => TargetPattern::Glob(domain),
+            None => match domain.strip_prefix('.') {
+                Some(suffix) => TargetPattern::Suffix(suffix.to_owned()),
+                None => TargetPattern::Domain(domain),
+            },
+        };
+        self.targets.push(pattern);
+        Ok(self)
+    }
+
+    /// Restricts the rule to target ports within `ports`.
+    pub fn ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.ports.push(ports);
+        self
+    }
+
+    fn matches(&self, client: IpAddr, identity: Option<&Identity>, command: Command, target: &TargetAddr<'_>) -> bool {
+        let port = match target {
+            TargetAddr::Ip(addr) => addr.port(),
+            TargetAddr::Domain(_, port) => *port,
+        };
+        let client = client.to_canonical();
+        any(&self.clients, |&(network, prefix)| in_network(client, network, prefix))
+            && any(&self.users, |user| {
+                identity.is_some_and(|identity| identity.name() == user)
+            })
+            && any(&self.commands, |&rule_command| rule_command == command)
+            && any(&self.targets, |pattern| pattern.matches(target))
+            && any(&self.ports, |ports| ports.contains(&port))
+    }
+}
+
+impl TargetPattern {
+    fn matches(&self, target: &TargetAddr<'_>) -> bool {
+        let host = match target {
+            TargetAddr::Ip(addr) => return self.matches_ip(addr.ip()),
+            TargetAddr::Domain(host, _) => host.trim_end_matches('.').to_ascii_lowercase(),
+        };
+        // The proxy connects to an IP address sent as a domain name all the same.
+        if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse() {
+            return self.matches_ip(ip);
+        }
+        match self {
+            TargetPattern::Domain(domain) => host == *domain,
+            TargetPattern::Suffix(suffix) => {
+                host == *suffix || (host.ends_with(suffix.as_str()) && host[..host.len() - suffix.len()].ends_with('.'))
+            },
+            TargetPattern::Glob(glob) => glob_matches(glob.as_bytes(), host.as_bytes()),
+            TargetPattern::Network(..) => false,
+        }
+    }
+
+    fn matches_ip(&self, ip: IpAddr) -> bool {
+        match self {
+            TargetPattern::Network(network, prefix) => in_network(ip.to_canonical(), *network, *prefix),
+            _ => false,
+        }
+    }
+}
+
+/// Returns whether `entries` is empty or has an entry matching `f`.
+fn any<T>(entries: &[T], f: impl FnMut(&T) -> bool) -> bool {
+    entries.is_empty() || entries.iter().any(f)
+}
+
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // The position following the last `*` and the text it matched up to.
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                star = Some((p, t));
+            },
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    star = Some((star_p, t));
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn parse_network(network: &str) -> Option<(IpAddr, u8)> {
+    let (ip, prefix): (IpAddr, _) = match network.split_once('/') {
+        Some((ip, prefix)) => {
+            let ip = ip.parse().ok()?;
+            let prefix: u8 = prefix.parse().ok()?;
+            Some((ip, prefix)).filter(|_| prefix <= max_prefix(ip))?
+        },
+        None => network.parse().ok().map(|ip| (ip, max_prefix(ip)))?,
+    };
+    // Addresses are canonicalized before matching, so IPv4-mapped ranges are too.
+    match ip.to_canonical() {
+        IpAddr::V4(v4) if ip.is_ipv6() && prefix >= 96 => Some((IpAddr::V4(v4), prefix - 96)),
+        _ => Some((ip, prefix)),
+    }
+}
+
+fn invalid(field: &str, entry: &str) -> Error {
+    Error::InvalidAcl(format!("invalid {} `{}`", field, entry))
+}
+
+#[cfg(any(feature = "toml", feature = "yaml"))]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    default: Action,
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+}
+
+#[cfg(any(feature = "toml", feature = "yaml"))]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    action: Action,
+    #[serde(default)]
+    clients: Vec<String>,
+    #[serde(default)]
+    users: Vec<String>,
+    #[serde(default)]
+    commands: Vec<String>,
+    #[serde(default)]
+    targets: Vec<String>,
+    #[serde(default)]
+    ports: Vec<PortsConfig>,
+}
+
+#[cfg(any(feature = "toml", feature = "yaml"))]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PortsConfig {
+    Port(u16),
+    Range(String),
+}
+
+#[cfg(any(feature = "toml", feature = "yaml"))]
+impl Config {
+    fn into_acl(self) -> Result<Acl> {
+        let mut acl = Acl::new(self.default);
+        for config in self.rules {
+            let mut rule = Rule::new(config.action);
+            for client in &config.clients {
+                rule = rule.client(client)?;
+            }
+            for user in config.users {
+                rule = rule.user(user);
+            }
+            for command in &config.commands {
+                rule = rule.command(parse_command(command).ok_or_else(|| invalid("command", command))?);
+            }
+            for target in &config.targets {
+                rule = rule.target(target)?;
+            }
+            for ports in &config.ports {
+                rule = rule.ports(match ports {
+                    PortsConfig::Port(port) => *port..=*port,
+                    PortsConfig::Range(range) => parse_ports(range).ok_or_else(|| invalid("ports", range))?,
+                });
+            }
+            acl = acl.rule(rule);
+        }
+        Ok(acl)
+    }
+}
+
+#[cfg(any(feature = "toml", feature = "yaml"))]
+fn parse_command(command: &str) -> Option<Command> {
+    match command {
+        "connect" => Some(Command::Connect),
+        "bind" => Some(Command::Bind),
+        "associate" => Some(Command::Associate),
+        "tor-resolve" => Some(Command::TorResolve),
+        "tor-resolve-ptr" => Some(Command::TorResolvePtr),
+        _ => None,
+    }
+}
+
+#[cfg(any(feature = "toml", feature = "yaml"))]
+fn parse_ports(range: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    Some(start..=end).filter(|_| start <= end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntoTargetAddr;
+
+    fn evaluate(acl: &Acl, client: &str, user: Option<&str>, command: Command, target: &str) -> Result<Action> {
+        let identity = user.map(Identity::new);
+        Ok(acl.evaluate(
+            client.parse().unwrap(),
+            identity.as_ref(),
+            command,
+            &target.into_target_addr()?,
+        ))
+    }
+
+    #[test]
+    fn first_matching_rule_wins() -> Result<()> {
+        let acl = Acl::new(Action::Allow)
+            .rule(Rule::deny().client("10.0.0.0/8")?.target("*.internal")?)
+            .rule(Rule::allow().user("admin"))
+            .rule(Rule::deny().command(Command::Bind))
+            .rule(Rule::deny().ports(0..=1023).ports(8080..=8080));
+        let connect = Command::Connect;
+        assert_eq!(
+            evaluate(&acl, "10.1.2.3", Some("admin"), connect, "db.internal:5432")?,
+            Action::Deny
+        );
+        assert_eq!(
+            evaluate(&acl, "192.168.1.1", None, connect, "db.internal:5432")?,
+            Action::Allow
+        );
+        assert_eq!(
+            evaluate(&acl, "10.1.2.3", Some("admin"), Command::Bind, "10.0.0.1:22")?,
+            Action::Allow
+        );
+        assert_eq!(
+            evaluate(&acl, "10.1.2.3", Some("bob"), Command::Bind, "10.0.0.1:2222")?,
+            Action::Deny
+        );
+        assert_eq!(
+            evaluate(&acl, "10.1.2.3", None, connect, "example.com:443")?,
+            Action::Deny
+        );
+        assert_eq!(
+            evaluate(&acl, "10.1.2.3", None, connect, "example.com:8080")?,
+            Action::Deny
+        );
+        assert_eq!(
+            evaluate(&acl, "10.1.2.3", None, connect, "example.com:8443")?,
+            Action::Allow
+        );
+        assert_eq!(
+            Acl::default().evaluate("::1".parse().unwrap(), None, connect, &"[::1]:80".into_target_addr()?),
+            Action::Deny
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ip_addresses_are_matched_in_any_form() -> Result<()> {
+        let acl = Acl::new(Action::Allow).rule(Rule::deny().client("10.0.0.0/8")?.target("10.0.0.0/8")?);
+        let deny = Acl::new(Action::Allow).rule(Rule::deny().target("10.0.0.0/8")?);
+        let client = "192.168.1.2".parse().unwrap();
+        for host in ["10.0.0.1", "10.0.0.1.", "::ffff:10.0.0.1", "[::ffff:10.0.0.1]"] {
+            let target = TargetAddr::Domain(host.into(), 80);
+            assert_eq!(deny.evaluate(client, None, Command::Connect, &target), Action::Deny, "{}", host);
+        }
+        assert_eq!(
+            evaluate(&deny, "192.168.1.2", None, Command::Connect, "[::ffff:10.0.0.1]:80")?,
+            Action::Deny
+        );
+        assert_eq!(
+            evaluate(&acl, "::ffff:10.1.2.3", None, Command::Connect, "10.0.0.1:80")?,
+            Action::Deny
+        );
+        assert_eq!(
+            evaluate(&acl, "::ffff:192.168.1.2", None, Command::Connect, "10.0.0.1:80")?,
+            Action::Allow
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn target_patterns() -> Result<()> {
+        let matches = |pattern: &str, target: &str| -> Result<bool> {
+            let acl = Acl::new(Action::Deny).rule(Rule::allow().target(pattern)?);
+            Ok(evaluate(&acl, "127.0.0.1", None, Command::Connect, target)? == Action::Allow)
+        };
+        assert!(matches("example.com", "Example.COM.:80")?);
+        assert!(!matches("example.com", "www.example.com:80")?);
+        assert!(matches(".example.com", "example.com:80")?);
+        assert!(matches(".example.com", "a.b.example.com:80")?);
+        assert!(!matches(".example.com", "badexample.com:80")?);
+        assert!(matches("*.example.com", "www.example.com:80")?);
+        assert!(!matches("*.example.com", "example.com:80")?);
+        assert!(matches("ww?.*.com", "www.example.com:80")?);
+        assert!(matches("*", "localhost:80")?);
+        assert!(matches("192.168.0.0/16", "192.168.3.4:80")?);
+        assert!(!matches("192.168.0.0/16", "192.169.3.4:80")?);
+        assert!(matches("2001:db8::/32", "[2001:db8::1]:80")?);
+        assert!(!matches("127.0.0.1", "localhost:80")?);
+        assert!(!matches("*", "127.0.0.1:80")?);
+        assert!(matches("::ffff:0:0/96", "[::ffff:10.0.0.1]:80")?);
+        assert!(matches("::ffff:10.0.0.0/104", "10.0.0.1:80")?);
+        assert!(Rule::allow().target(".").is_err());
+        assert!(Rule::allow().client("10.0.0.0/33").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn from_toml() -> Result<()> {
+        let acl = Acl::from_toml(
+            r#"
+            default = "allow"
+
+            [[rules]]
+            action = "deny"
+            clients = ["10.0.0.0/8"]
+            commands = ["associate"]
+            ports = [53, "5000-5999"]
+            "#,
+        )?;
+        assert_eq!(
+            evaluate(&acl, "10.0.0.1", None, Command::Associate, "1.1.1.1:53")?,
+            Action::Deny
+        );
+        assert_eq!(
+            evaluate(&acl, "10.0.0.1", None, Command::Associate, "1.1.1.1:5353")?,
+            Action::Deny
+        );
+        assert_eq!(
+            evaluate(&acl, "10.0.0.1", None, Command::Associate, "1.1.1.1:443")?,
+            Action::Allow
+        );
+        assert_eq!(
+            evaluate(&acl, "10.0.0.1", None, Command::Connect, "1.1.1.1:53")?,
+            Action::Allow
+        );
+
+        assert!(matches!(
+            Acl::from_toml("[[rules]]\naction = \"maybe\""),
+            Err(Error::InvalidAcl(_))
+        ));
+        assert!(matches!(
+            Acl::from_toml("[[rules]]\naction = \"deny\"\nports = [\"9-1\"]"),
+            Err(Error::InvalidAcl(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn from_yaml() -> Result<()> {
+        let acl = Acl::from_yaml("rules:\n  - action: allow\n    users: [alice]\n    targets: ['.example.com']\n")?;
+        assert_eq!(
+            evaluate(&acl, "10.0.0.1", Some("alice"), Command::Connect, "example.com:80")?,
+            Action::Allow
+        );
+        assert_eq!(
+            evaluate(&acl, "10.0.0.1", None, Command::Connect, "example.com:80")?,
+            Action::Deny
+        );
+        assert!(matches!(
+            Acl::from_yaml("rules:\n  - action: allow\n    commands: [fly]\n"),
+            Err(Error::InvalidAcl(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,426 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+};
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+use std::{fs, path::Path, str::FromStr};
+#[cfg(all(feature = "tokio", any(feature = "bcrypt", feature = "argon2")))]
+use std::io;
+
+use futures_util::future;
+
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+use crate::Error;
+use crate::Result;
+
+/// The future returned by the methods of `AuthBackend`, resolving to the
+/// identity of the client, or `None` if it is rejected.
+pub type AuthFuture = Pin<Box<dyn Future<Output = Result<Option<Identity>>> + Send>>;
+
+/// The identity of an authenticated client, attached to its request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Identity {
+    name: String,
+}
+
+impl Identity {
+    /// Creates the identity of the user called `name`.
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Identity { name: name.into() }
+    }
+
+    /// Returns the name of the user.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Checks the credentials of the clients of a server.
+///
+/// A failing check makes the server drop the client, replying as it does for
+/// rejected credentials.
+pub trait AuthBackend: Send + Sync {
+    /// Checks the username and password of a SOCKS5 client (RFC 1929).
+    fn check_password(&self, username: &str, password: &str) -> AuthFuture;
+
+    /// Checks the user ID of a SOCKS4 client. Every user ID is rejected by
+    /// default, as it comes without a password.
+    fn check_user_id(&self, user_id: &str) -> AuthFuture {
+        let _ = user_id;
+        Box::pin(future::ready(Ok(None)))
+    }
+}
+
+impl<A: AuthBackend + ?Sized> AuthBackend for Arc<A> {
+    fn check_password(&self, username: &str, password: &str) -> AuthFuture {
+        (**self).check_password(username, password)
+    }
+
+    fn check_user_id(&self, user_id: &str) -> AuthFuture {
+        (**self).check_user_id(user_id)
+    }
+}
+
+impl<A: AuthBackend + ?Sized> AuthBackend for &A {
+    fn check_password(&self, username: &str, password: &str) -> AuthFuture {
+        (**self).check_password(username, password)
+    }
+
+    fn check_user_id(&self, user_id: &str) -> AuthFuture {
+        (**self).check_user_id(user_id)
+    }
+}
+
+/// An `AuthBackend` holding plain-text credentials in memory.
+///
+/// # Examples
+///
+/// ```
+/// use tokio_socks::server::{Socks5Server, StaticAuth};
+///
+/// let server = Socks5Server::new().auth(StaticAuth::new().user("admin", "123456"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StaticAuth {
+    users: HashMap<String, String>,
+    user_ids: HashSet<String>,
+}
+
+impl StaticAuth {
+    /// Creates a backend rejecting every client.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts SOCKS5 clients authenticating with `username` and `password`.
+    pub fn user<U, P>(mut self, username: U, password: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.users.insert(username.into(), password.into());
+        self
+    }
+
+    /// Accepts SOCKS4 clients sending `user_id`.
+    pub fn user_id<U: Into<String>>(mut self, user_id: U) -> Self {
+        self.user_ids.insert(user_id.into());
+        self
+    }
+}
+
+impl AuthBackend for StaticAuth {
+    fn check_password(&self, username: &str, password: &str) -> AuthFuture {
+        let valid = self
+            .users
+            .get(username)
+            .is_some_and(|expected| constant_time_eq(expected.as_bytes(), password.as_bytes()));
+        Box::pin(future::ready(Ok(Some(Identity::new(username)).filter(|_| valid))))
+    }
+
+    fn check_user_id(&self, user_id: &str) -> AuthFuture {
+        let valid = self.user_ids.contains(user_id);
+        Box::pin(future::ready(Ok(Some(Identity::new(user_id)).filter(|_| valid))))
+    }
+}
+
+/// Compares `a` and `b` in a time depending on their lengths only, not on
+/// where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// An `AuthBackend` reading the users of an htpasswd file, one
+/// `username:hash` entry per line.
+///
+/// Bcrypt hashes (`$2a$`, `$2b$`, `$2x$` and `$2y$`) are supported with the
+/// `bcrypt` feature, and Argon2 hashes in the PHC string format (`$argon2id$`
+/// and its variants) with the `argon2` feature. Blank lines and lines starting
+/// with `#` are ignored.
+///
+/// Hashes are verified by an `Offload`, away from the tasks of the async
+/// runtime: by `TokioOffload` with the `tokio` feature, on the task checking
+/// the credentials otherwise, unless another one is set with `offload`.
+/// Unknown usernames are checked against the hash of a known one, so that
+/// they can't be told apart by the time their check takes.
+///
+/// # Examples
+///
+/// ```no_run
+/// use tokio_socks::server::{Htpasswd, Socks5Server};
+///
+/// let server = Socks5Server::new().auth(Htpasswd::from_file("/etc/socks/htpasswd")?);
+/// # Ok::<(), tokio_socks::Error>(())
+/// ```
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+#[derive(Clone, Default)]
+pub struct Htpasswd {
+    users: HashMap<String, String>,
+    /// The hash unknown usernames are checked against, if there is any user.
+    dummy: Option<String>,
+    offload: Option<Arc<dyn Offload>>,
+}
+
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+impl fmt::Debug for Htpasswd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Htpasswd")
+            .field("users", &self.users.keys())
+            .field("offload", &self.offload.as_ref().map(|_| ".."))
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+impl Htpasswd {
+    /// Reads the users of the file at `path`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the file can't be read, or if an entry is malformed or
+    /// uses an unsupported hash.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Verifies hashes with `offload` instead of the default one.
+    pub fn offload<O>(mut self, offload: O) -> Self
+    where O: Offload + 'static {
+        self.offload = Some(Arc::new(offload));
+        self
+    }
+
+    fn verify(&self, verify: Box<dyn FnOnce() -> bool + Send>) -> OffloadFuture {
+        match &self.offload {
+            Some(offload) => offload.run(verify),
+            #[cfg(feature = "tokio")]
+            None => TokioOffload.run(verify),
+            #[cfg(not(feature = "tokio"))]
+            None => Box::pin(async move { Ok(verify()) }),
+        }
+    }
+}
+
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+impl FromStr for Htpasswd {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut users = HashMap::new();
+        let mut dummy = None;
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason| Error::InvalidHtpasswd {
+                line: index + 1,
+                reason,
+            };
+            let (username, hash) = line.split_once(':').ok_or_else(|| invalid("missing `:`"))?;
+            if username.is_empty() {
+                return Err(invalid("empty username"));
+            }
+            if Scheme::of(hash).is_none() {
+                return Err(invalid("unsupported hash"));
+            }
+            dummy.get_or_insert_with(|| hash.to_owned());
+            users.insert(username.to_owned(), hash.to_owned());
+        }
+        Ok(Htpasswd {
+            users,
+            dummy,
+            offload: None,
+        })
+    }
+}
+
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+impl AuthBackend for Htpasswd {
+    fn check_password(&self, username: &str, password: &str) -> AuthFuture {
+        let (known, hash) = match (self.users.get(username), &self.dummy) {
+            (Some(hash), _) => (true, hash.clone()),
+            (None, Some(dummy)) => (false, dummy.clone()),
+            (None, None) => return Box::pin(future::ready(Ok(None))),
+        };
+        let password = password.to_owned();
+        let verify = self.verify(Box::new(move || {
+            Scheme::of(&hash).is_some_and(|scheme| scheme.verify(&password, &hash))
+        }));
+        let identity = Identity::new(username);
+        Box::pin(async move {
+            let valid = verify.await?;
+            Ok(Some(identity).filter(|_| known && valid))
+        })
+    }
+}
+
+/// The future returned by `Offload::run`, resolving to the result of the
+/// verification.
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+pub type OffloadFuture = Pin<Box<dyn Future<Output = Result<bool>> + Send>>;
+
+/// Runs the password hash verifications of `Htpasswd`, which are too costly
+/// for the tasks of an async runtime.
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+pub trait Offload: Send + Sync {
+    /// Runs `verify`, which blocks the calling thread, and returns its result.
+    fn run(&self, verify: Box<dyn FnOnce() -> bool + Send>) -> OffloadFuture;
+}
+
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+impl<O: Offload + ?Sized> Offload for Arc<O> {
+    fn run(&self, verify: Box<dyn FnOnce() -> bool + Send>) -> OffloadFuture {
+        (**self).run(verify)
+    }
+}
+
+/// Runs verifications on tokio's blocking thread pool.
+#[cfg(all(feature = "tokio", any(feature = "bcrypt", feature = "argon2")))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioOffload;
+
+#[cfg(all(feature = "tokio", any(feature = "bcrypt", feature = "argon2")))]
+impl Offload for TokioOffload {
+    fn run(&self, verify: Box<dyn FnOnce() -> bool + Send>) -> OffloadFuture {
+        let task = tokio::task::spawn_blocking(verify);
+        Box::pin(async move { task.await.map_err(|err| io::Error::other(err).into()) })
+    }
+}
+
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+#[derive(Debug, Clone, Copy)]
+enum Scheme {
+    #[cfg(feature = "bcrypt")]
+    Bcrypt,
+    #[cfg(feature = "argon2")]
+    Argon2,
+}
+
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+impl Scheme {
+    fn of(hash: &str) -> Option<Self> {
+        #[cfg(feature = "bcrypt")]
+        {
+            if ["$2a$", "$2b$", "$2x$", "$2y$"]
+                .iter()
+                .any(|prefix| hash.starts_with(prefix))
+            {
+                return Some(Scheme::Bcrypt);
+            }
+        }
+        #[cfg(feature = "argon2")]
+        {
+            if hash.starts_with("$argon2") && argon2::PasswordHash::new(hash).is_ok() {
+                return Some(Scheme::Argon2);
+            }
+        }
+        None
+    }
+
+    fn verify(self, password: &str, hash: &str) -> bool {
+        match self {
+            #[cfg(feature = "bcrypt")]
+            Scheme::Bcrypt => bcrypt::verify(password, hash).unwrap_or(false),
+            #[cfg(feature = "argon2")]
+            Scheme::Argon2 => {
+                use argon2::PasswordVerifier;
+
+                argon2::PasswordHash::new(hash).is_ok_and(|hash| {
+                    argon2::Argon2::default()
+                        .verify_password(password.as_bytes(), &hash)
+                        .is_ok()
+                })
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_executor::block_on;
+
+    use super::*;
+
+    #[test]
+    fn static_auth() -> Result<()> {
+        let auth = StaticAuth::new().user("me", "pw").user_id("you");
+        assert_eq!(block_on(auth.check_password("me", "pw"))?, Some(Identity::new("me")));
+        assert_eq!(block_on(auth.check_password("me", "bad"))?, None);
+        assert_eq!(block_on(auth.check_password("you", "pw"))?, None);
+        assert_eq!(block_on(auth.check_user_id("you"))?, Some(Identity::new("you")));
+        assert_eq!(block_on(auth.check_user_id("me"))?, None);
+        assert!(constant_time_eq(b"pw", b"pw"));
+        assert!(!constant_time_eq(b"pw", b"pW"));
+        assert!(!constant_time_eq(b"pw", b"pw2"));
+        Ok(())
+    }
+
+    #[cfg(all(feature = "tokio", feature = "bcrypt", feature = "argon2"))]
+    #[test]
+    fn htpasswd() -> Result<()> {
+        use argon2::{password_hash::SaltString, PasswordHasher};
+
+        let bcrypt = bcrypt::hash("pw", 4).unwrap();
+        let salt = SaltString::from_b64("c29tZXNhbHQ").unwrap();
+        let argon2 = argon2::Argon2::default()
+            .hash_password(b"secret", &salt)
+            .unwrap()
+            .to_string();
+        let file = format!("# users\nme:{}\n\nyou:{}\n", bcrypt, argon2);
+        let auth: Htpasswd = file.parse()?;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            assert_eq!(auth.check_password("me", "pw").await?, Some(Identity::new("me")));
+            assert_eq!(auth.check_password("me", "bad").await?, None);
+            assert_eq!(auth.check_password("you", "secret").await?, Some(Identity::new("you")));
+            // Unknown users are checked against the hash of `me`, without matching it.
+            assert_eq!(auth.check_password("them", "pw").await?, None);
+            assert_eq!(auth.check_user_id("me").await?, None);
+            Result::Ok(())
+        })?;
+
+        assert!(matches!(
+            "me:{SHA}x".parse::<Htpasswd>(),
+            Err(Error::InvalidHtpasswd { line: 1, .. })
+        ));
+        assert!(matches!(
+            "# users\nme".parse::<Htpasswd>(),
+            Err(Error::InvalidHtpasswd { line: 2, .. })
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "bcrypt")]
+    #[test]
+    fn htpasswd_offload() -> Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct Counting(AtomicUsize);
+
+        impl Offload for Counting {
+            fn run(&self, verify: Box<dyn FnOnce() -> bool + Send>) -> OffloadFuture {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Box::pin(future::ready(Ok(verify())))
+            }
+        }
+
+        let offload = Arc::new(Counting(AtomicUsize::new(0)));
+        let file = format!("me:{}\n", bcrypt::hash("pw", 4).unwrap());
+        let auth = file.parse::<Htpasswd>()?.offload(offload.clone());
+        assert_eq!(block_on(auth.check_password("me", "pw"))?, Some(Identity::new("me")));
+        assert_eq!(block_on(auth.check_password("them", "pw"))?, None);
+        assert_eq!(offload.0.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
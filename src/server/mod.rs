@@ -0,0 +1,303 @@
+//! The server side of the SOCKS protocols.
+//!
+//! A server handles connections accepted by the application: it negotiates
+//! with the client, then hands the parsed request to the application, which
+//! carries it out and answers with a success or an error reply.
+use std::{io, net::IpAddr};
+
+use crate::{
+    io::{AsyncSocket, AsyncSocketExt},
+    tcp::{socks4::wire::Command as Socks4Command, socks5::wire::Command},
+    DecodeError,
+    Error,
+    IntoTargetAddr,
+    Result,
+    TargetAddr,
+};
+
+mod acl;
+mod auth;
+pub mod socks4;
+pub mod socks5;
+
+#[cfg(all(feature = "tokio", any(feature = "bcrypt", feature = "argon2")))]
+pub use self::auth::TokioOffload;
+#[cfg(any(feature = "bcrypt", feature = "argon2"))]
+pub use self::auth::{Htpasswd, Offload, OffloadFuture};
+pub use self::{
+    acl::{Acl, Action, Rule},
+    auth::{AuthBackend, AuthFuture, Identity, StaticAuth},
+    socks4::{Socks4Request, Socks4Server},
+    socks5::{Socks5Request, Socks5Server},
+};
+
+/// The longest message a client may send, well above the longest valid one.
+const MAX_MESSAGE_LEN: usize = 1024;
+
+/// A server of both SOCKS4 and SOCKS5 clients, telling them apart by the
+/// version of their first message.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "tokio")]
+/// # async fn serve() -> tokio_socks::Result<()> {
+/// use tokio::net::TcpListener;
+/// use tokio_socks::server::{Socks4Server, Socks5Server, SocksServer};
+///
+/// let server = SocksServer::new(Socks4Server::new(), Socks5Server::new());
+/// let listener = TcpListener::bind("127.0.0.1:1080").await?;
+/// let (socket, _) = listener.accept().await?;
+/// let request = server.accept(socket).await?;
+/// println!("{} wants to reach {}", request.version(), request.target());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SocksServer {
+    socks4: Socks4Server,
+    socks5: Socks5Server,
+}
+
+impl SocksServer {
+    /// Creates a server handing SOCKS4 clients to `socks4` and SOCKS5 clients
+    /// to `socks5`.
+    pub fn new(socks4: Socks4Server, socks5: Socks5Server) -> Self {
+        SocksServer { socks4, socks5 }
+    }
+
+    /// Reads the version of the client connected through `socket`, then
+    /// accepts it with the matching server.
+    ///
+    /// # Error
+    ///
+    /// It fails if the version is neither 4 nor 5, and otherwise propagates
+    /// the error of the matching server.
+    pub async fn accept<S>(&self, mut socket: S) -> Result<SocksRequest<S>>
+    where S: AsyncSocket + Unpin {
+        let mut buf = vec![0];
+        socket.read_exact(&mut buf).await?;
+        match buf[0] {
+            0x04 => Ok(SocksRequest::Socks4(self.socks4.handshake(socket, buf).await?)),
+            0x05 => Ok(SocksRequest::Socks5(self.socks5.handshake(socket, buf).await?)),
+            version => Err(DecodeError::InvalidVersion(version).into()),
+        }
+    }
+}
+
+/// A request read by a `SocksServer`, to be answered by the application.
+#[derive(Debug)]
+pub enum SocksRequest<S> {
+    /// The request of a SOCKS4 or SOCKS4A client.
+    Socks4(Socks4Request<S>),
+    /// The request of a SOCKS5 client.
+    Socks5(Socks5Request<S>),
+}
+
+impl<S> SocksRequest<S>
+where S: AsyncSocket + Unpin
+{
+    /// Returns the version of the protocol spoken by the client.
+    pub fn version(&self) -> u8 {
+        match self {
+            SocksRequest::Socks4(_) => 4,
+            SocksRequest::Socks5(_) => 5,
+        }
+    }
+
+    /// Returns the command requested by the client, SOCKS4 commands being
+    /// mapped to their SOCKS5 counterpart.
+    pub fn command(&self) -> Command {
+        match self {
+            SocksRequest::Socks4(request) => socks5_command(request.command()),
+            SocksRequest::Socks5(request) => request.command(),
+        }
+    }
+
+    /// Returns the target of the command.
+    pub fn target(&self) -> &TargetAddr<'static> {
+        match self {
+            SocksRequest::Socks4(request) => request.target(),
+            SocksRequest::Socks5(request) => request.target(),
+        }
+    }
+
+    /// Returns the identity the client authenticated as, if any.
+    pub fn identity(&self) -> Option<&Identity> {
+        match self {
+            SocksRequest::Socks4(request) => request.identity(),
+            SocksRequest::Socks5(request) => request.identity(),
+        }
+    }
+
+    /// Tells the client the command succeeded, `addr` being the address
+    /// bound by the server, and returns the connection for relaying.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`, or fails if the reply can't be written.
+    pub async fn succeed<'t, T>(self, addr: T) -> Result<S>
+    where T: IntoTargetAddr<'t> {
+        match self {
+            SocksRequest::Socks4(request) => request.succeed(addr).await,
+            SocksRequest::Socks5(request) => request.succeed(addr).await,
+        }
+    }
+
+    /// Tells the client the command failed because of `err`, with the reply
+    /// the client maps back to the closest error.
+    pub async fn fail(self, err: &Error) -> Result<()> {
+        match self {
+            SocksRequest::Socks4(request) => request.fail(err).await,
+            SocksRequest::Socks5(request) => request.fail(err).await,
+        }
+    }
+
+    /// Checks the request of the client connected from `client` against
+    /// `acl`, denying it with the "connection not allowed by ruleset" reply
+    /// if the list does.
+    ///
+    /// # Error
+    ///
+    /// It fails with `Error::ConnectionNotAllowedByRuleset` if the request is
+    /// denied, or if the reply can't be written.
+    pub async fn authorize(self, acl: &Acl, client: IpAddr) -> Result<Self> {
+        match self {
+            SocksRequest::Socks4(request) => request.authorize(acl, client).await.map(SocksRequest::Socks4),
+            SocksRequest::Socks5(request) => request.authorize(acl, client).await.map(SocksRequest::Socks5),
+        }
+    }
+
+    /// Consumes the request without replying, returning the connection to
+    /// the client.
+    pub fn into_inner(self) -> S {
+        match self {
+            SocksRequest::Socks4(request) => request.into_inner(),
+            SocksRequest::Socks5(request) => request.into_inner(),
+        }
+    }
+}
+
+/// Returns the SOCKS5 counterpart of a SOCKS4 command.
+fn socks5_command(command: Socks4Command) -> Command {
+    match command {
+        Socks4Command::Connect => Command::Connect,
+        Socks4Command::Bind => Command::Bind,
+    }
+}
+
+/// Reads from `socket` until `buf` holds a message `decode` accepts, without
+/// reading past its end, and removes the message from `buf`.
+async fn read_message<S, T, F>(socket: &mut S, buf: &mut Vec<u8>, decode: F) -> Result<T>
+where
+    S: AsyncSocket + Unpin,
+    F: Fn(&[u8]) -> std::result::Result<(T, usize), DecodeError>,
+{
+    loop {
+        match decode(buf) {
+            Ok((message, len)) => {
+                buf.drain(..len);
+                return Ok(message);
+            },
+            Err(DecodeError::Incomplete(len)) if len > MAX_MESSAGE_LEN => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "message is too long").into());
+            },
+            Err(DecodeError::Incomplete(len)) => {
+                let start = buf.len();
+                buf.resize(len, 0);
+                socket.read_exact(&mut buf[start..]).await?;
+            },
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::{Acl, Action, Command, Error, Result, Rule, Socks4Server, Socks5Server, SocksServer};
+    use crate::tcp::{Socks4Stream, Socks5Stream};
+
+    #[test]
+    fn versions_are_sniffed() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = SocksServer::new(Socks4Server::new(), Socks5Server::new());
+            for version in [4, 5] {
+                let (socket, proxy) = tokio::io::duplex(1024);
+                let accept = tokio::spawn({
+                    let server = server.clone();
+                    async move {
+                        let request = server.accept(proxy).await?;
+                        assert_eq!(request.version(), version);
+                        assert_eq!(request.command(), Command::Connect);
+                        assert_eq!(request.target().to_string(), "10.0.0.1:80");
+                        request.succeed("10.0.0.2:8080").await.map(|_| ())
+                    }
+                });
+                let bound = match version {
+                    4 => Socks4Stream::connect_with_socket(socket, "10.0.0.1:80")
+                        .await?
+                        .target_addr()
+                        .to_string(),
+                    _ => Socks5Stream::connect_with_socket(socket, "10.0.0.1:80")
+                        .await?
+                        .target_addr()
+                        .to_string(),
+                };
+                assert_eq!(bound, "10.0.0.2:8080");
+                accept.await.unwrap()?;
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn unknown_versions_and_long_messages_fail() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = SocksServer::new(Socks4Server::new(), Socks5Server::new());
+
+            let (mut socket, proxy) = tokio::io::duplex(1024);
+            socket.write_all(&[0x06]).await?;
+            assert!(matches!(server.accept(proxy).await, Err(Error::InvalidMessage(_))));
+
+            let (mut socket, proxy) = tokio::io::duplex(4096);
+            socket.write_all(&[0x04, 0x01, 0, 80, 10, 0, 0, 1]).await?;
+            socket.write_all(&[b'a'; 2048]).await?;
+            assert!(matches!(server.accept(proxy).await, Err(Error::Io(_))));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn denied_requests_are_replied() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = SocksServer::new(Socks4Server::new(), Socks5Server::new());
+            let acl = Acl::new(Action::Allow).rule(Rule::deny().target(".internal")?);
+            for (target, allowed) in [("db.internal:5432", false), ("10.0.0.1:80", true)] {
+                let (socket, proxy) = tokio::io::duplex(1024);
+                let accept = tokio::spawn({
+                    let (server, acl) = (server.clone(), acl.clone());
+                    async move {
+                        let request = server.accept(proxy).await?;
+                        let client = "127.0.0.1".parse().unwrap();
+                        request.authorize(&acl, client).await?.succeed("10.0.0.2:8080").await.map(|_| ())
+                    }
+                });
+                let res = Socks5Stream::connect_with_socket(socket, target).await;
+                if allowed {
+                    res?;
+                    accept.await.unwrap()?;
+                } else {
+                    assert!(matches!(res, Err(Error::ConnectionNotAllowedByRuleset)));
+                    assert!(matches!(accept.await.unwrap(), Err(Error::ConnectionNotAllowedByRuleset)));
+                }
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::Arc,
+};
+
+use bytes::BytesMut;
+
+use super::{read_message, socks5_command, Acl, Action, AuthBackend, Identity};
+use crate::{
+    io::{AsyncSocket, AsyncSocketExt},
+    tcp::socks4::wire::{Command, Reply, ReplyCode, Request},
+    DecodeError,
+    Error,
+    IntoTargetAddr,
+    Result,
+    TargetAddr,
+};
+
+/// A SOCKS4 server configuration, serving SOCKS4A clients as well.
+///
+/// By default, every user ID is accepted as long as no authentication backend
+/// is set.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "tokio")]
+/// # async fn serve() -> tokio_socks::Result<()> {
+/// use tokio::net::{TcpListener, TcpStream};
+/// use tokio_socks::server::{Socks4Server, StaticAuth};
+///
+/// let server = Socks4Server::new().auth(StaticAuth::new().user_id("admin"));
+/// let listener = TcpListener::bind("127.0.0.1:1080").await?;
+/// let (socket, _) = listener.accept().await?;
+/// let request = server.accept(socket).await?;
+/// match TcpStream::connect(request.target().to_string()).await {
+///     Ok(remote) => {
+///         let bound = remote.local_addr()?;
+///         let client = request.succeed(bound).await?;
+///         // Relay data between `client` and `remote`.
+///     },
+///     Err(err) => request.fail(&err.into()).await?,
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct Socks4Server {
+    auth: Option<Arc<dyn AuthBackend>>,
+}
+
+impl fmt::Debug for Socks4Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks4Server")
+            .field("auth", &self.auth.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl Socks4Server {
+    /// Creates a server accepting every user ID.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the user IDs of clients with `backend`, instead of accepting
+    /// every client.
+    pub fn auth<A>(mut self, backend: A) -> Self
+    where A: AuthBackend + 'static {
+        self.auth = Some(Arc::new(backend));
+        self
+    }
+
+    /// Reads the request of the client connected through `socket` and checks
+    /// its user ID.
+    ///
+    /// # Error
+    ///
+    /// It fails if the client sends an invalid request, or if its user ID is
+    /// not accepted. The client is told why before the error is returned.
+    pub async fn accept<S>(&self, socket: S) -> Result<Socks4Request<S>>
+    where S: AsyncSocket + Unpin {
+        self.handshake(socket, Vec::new()).await
+    }
+
+    /// Runs the handshake, `buf` holding the bytes already read from
+    /// `socket`.
+    pub(super) async fn handshake<S>(&self, mut socket: S, mut buf: Vec<u8>) -> Result<Socks4Request<S>>
+    where S: AsyncSocket + Unpin {
+        let request = match read_message(&mut socket, &mut buf, Request::decode).await {
+            Ok(request) => request,
+            Err(err @ Error::InvalidMessage(DecodeError::UnknownCommand(_))) => {
+                write_reply(&mut socket, ReplyCode::Rejected, unspecified(0)).await?;
+                return Err(err);
+            },
+            Err(err) => return Err(err),
+        };
+
+        let identity = match &self.auth {
+            Some(backend) => match backend.check_user_id(&request.user_id).await {
+                Ok(Some(identity)) => Some(identity),
+                res => {
+                    write_reply(&mut socket, ReplyCode::IdentdMismatch, unspecified(0)).await?;
+                    res?;
+                    return Err(Error::InvalidUserIdAuthFailure);
+                },
+            },
+            None => None,
+        };
+
+        Ok(Socks4Request {
+            socket,
+            command: request.command,
+            target: request.target,
+            user_id: request.user_id.into_owned(),
+            identity,
+        })
+    }
+}
+
+/// A request read by a `Socks4Server`, to be answered by the application.
+#[derive(Debug)]
+pub struct Socks4Request<S> {
+    socket: S,
+    command: Command,
+    target: TargetAddr<'static>,
+    user_id: String,
+    identity: Option<Identity>,
+}
+
+impl<S> Socks4Request<S>
+where S: AsyncSocket + Unpin
+{
+    /// Returns the command requested by the client.
+    pub fn command(&self) -> Command {
+        self.command
+    }
+
+    /// Returns the target of the command, a domain name for SOCKS4A clients.
+    pub fn target(&self) -> &TargetAddr<'static> {
+        &self.target
+    }
+
+    /// Returns the user ID sent by the client, possibly empty.
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    /// Returns the identity the authentication backend attached to the
+    /// client, if any.
+    pub fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    /// Sends a reply to the client.
+    ///
+    /// The second reply of a BIND command, carrying the address of the
+    /// remote process, is sent this way after the first one.
+    pub async fn reply(&mut self, code: ReplyCode, addr: SocketAddrV4) -> Result<()> {
+        write_reply(&mut self.socket, code, addr).await
+    }
+
+    /// Tells the client the command was granted, `addr` being the address
+    /// bound by the server, and returns the connection for relaying.
+    ///
+    /// Only IPv4 addresses can be replied. Other addresses are replaced by
+    /// the unspecified address, which tells the client to use the address of
+    /// the server.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`, or fails if the reply can't be written.
+    pub async fn succeed<'t, T>(mut self, addr: T) -> Result<S>
+    where T: IntoTargetAddr<'t> {
+        let addr = match addr.into_target_addr()? {
+            TargetAddr::Ip(SocketAddr::V4(addr)) => addr,
+            TargetAddr::Ip(addr) => unspecified(addr.port()),
+            TargetAddr::Domain(_, port) => unspecified(port),
+        };
+        self.reply(ReplyCode::Granted, addr).await?;
+        Ok(self.socket)
+    }
+
+    /// Tells the client the command failed because of `err`, with the reply
+    /// the client maps back to the same error.
+    pub async fn fail(mut self, err: &Error) -> Result<()> {
+        self.reply(ReplyCode::from(err), unspecified(0)).await
+    }
+
+    /// Checks the request of the client connected from `client` against
+    /// `acl`, denying it with reply `0x5B` if the list does. The command is
+    /// matched by its SOCKS5 counterpart.
+    ///
+    /// # Error
+    ///
+    /// It fails with `Error::ConnectionNotAllowedByRuleset` if the request is
+    /// denied, or if the reply can't be written.
+    pub async fn authorize(self, acl: &Acl, client: IpAddr) -> Result<Self> {
+        match acl.evaluate(client, self.identity(), socks5_command(self.command), &self.target) {
+            Action::Allow => Ok(self),
+            Action::Deny => {
+                self.fail(&Error::ConnectionNotAllowedByRuleset).await?;
+                Err(Error::ConnectionNotAllowedByRuleset)
+            },
+        }
+    }
+
+    /// Returns a mutable reference to the connection to the client.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.socket
+    }
+
+    /// Consumes the request without replying, returning the connection to
+    /// the client.
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+}
+
+fn unspecified(port: u16) -> SocketAddrV4 {
+    SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)
+}
+
+async fn write_reply<S>(socket: &mut S, code: ReplyCode, addr: SocketAddrV4) -> Result<()>
+where S: AsyncSocket + Unpin {
+    let mut message = BytesMut::new();
+    Reply { code, addr }.encode(&mut message);
+    socket.write_all(&message).await?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{Command, Error, Identity, Result, Socks4Server};
+    use crate::{server::StaticAuth, tcp::Socks4Stream};
+
+    #[test]
+    fn socks4a_connect() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (socket, proxy) = tokio::io::duplex(1024);
+            let server = tokio::spawn(async move {
+                let request = Socks4Server::new()
+                    .auth(StaticAuth::new().user_id("me"))
+                    .accept(proxy)
+                    .await?;
+                assert_eq!(request.command(), Command::Connect);
+                assert_eq!(request.target().to_string(), "example.com:80");
+                assert_eq!(request.user_id(), "me");
+                assert_eq!(request.identity().map(Identity::name), Some("me"));
+                let mut client = request.succeed("10.0.0.2:8080").await?;
+                let mut buf = [0; 5];
+                client.read_exact(&mut buf).await?;
+                client.write_all(&buf).await?;
+                Result::Ok(())
+            });
+
+            let mut stream = Socks4Stream::connect_with_userid_and_socket(socket, "example.com:80", "me").await?;
+            assert_eq!(stream.target_addr().to_string(), "10.0.0.2:8080");
+            stream.write_all(b"hello").await?;
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"hello");
+            server.await.unwrap()
+        })
+    }
+
+    #[test]
+    fn rejections_are_replied() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (socket, proxy) = tokio::io::duplex(1024);
+            let accept = tokio::spawn(async move {
+                let server = Socks4Server::new().auth(StaticAuth::new().user_id("me"));
+                server.accept(proxy).await.map(|_| ())
+            });
+            let res = Socks4Stream::connect_with_userid_and_socket(socket, "10.0.0.1:80", "you").await;
+            assert!(matches!(res, Err(Error::InvalidUserIdAuthFailure)));
+            assert!(matches!(accept.await.unwrap(), Err(Error::InvalidUserIdAuthFailure)));
+
+            let (socket, proxy) = tokio::io::duplex(1024);
+            let server = tokio::spawn(async move {
+                let request = Socks4Server::new().accept(proxy).await?;
+                assert_eq!(request.user_id(), "");
+                assert_eq!(request.identity(), None);
+                request.fail(&Error::ConnectionRefused).await
+            });
+            let res = Socks4Stream::connect_with_socket(socket, "10.0.0.1:80").await;
+            assert!(matches!(res, Err(Error::GeneralSocksServerFailure)));
+            server.await.unwrap()
+        })
+    }
+}
@@ -0,0 +1,402 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use bytes::BytesMut;
+
+use super::{read_message, Acl, Action, AuthBackend, Identity};
+use crate::{
+    io::{AsyncSocket, AsyncSocketExt},
+    tcp::socks5::wire::{
+        Command,
+        MethodReply,
+        MethodSelection,
+        PasswordAuthRequest,
+        PasswordAuthResponse,
+        Reply,
+        ReplyCode,
+        Request,
+    },
+    DecodeError,
+    Error,
+    IntoTargetAddr,
+    Result,
+    TargetAddr,
+};
+
+/// A SOCKS5 server configuration.
+///
+/// By default, clients are accepted without authentication as long as no
+/// authentication backend is set.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "tokio")]
+/// # async fn serve() -> tokio_socks::Result<()> {
+/// use tokio::net::{TcpListener, TcpStream};
+/// use tokio_socks::{
+///     server::{Socks5Server, StaticAuth},
+///     tcp::socks5::wire::Command,
+/// };
+///
+/// let server = Socks5Server::new().auth(StaticAuth::new().user("admin", "123456"));
+/// let listener = TcpListener::bind("127.0.0.1:1080").await?;
+/// let (socket, _) = listener.accept().await?;
+/// let request = server.accept(socket).await?;
+/// if request.command() != Command::Connect {
+///     return request.fail(&tokio_socks::Error::CommandNotSupported).await;
+/// }
+/// match TcpStream::connect(request.target().to_string()).await {
+///     Ok(remote) => {
+///         let bound = remote.local_addr()?;
+///         let client = request.succeed(bound).await?;
+///         // Relay data between `client` and `remote`.
+///     },
+///     Err(err) => request.fail(&err.into()).await?,
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct Socks5Server {
+    anonymous: Option<bool>,
+    auth: Option<Arc<dyn AuthBackend>>,
+}
+
+impl fmt::Debug for Socks5Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Server")
+            .field("anonymous", &self.allows_anonymous())
+            .field("auth", &self.auth.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl Socks5Server {
+    /// Creates a server accepting clients without authentication.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the credentials of clients authenticating with a username and
+    /// a password (RFC 1929) with `backend`.
+    pub fn auth<A>(mut self, backend: A) -> Self
+    where A: AuthBackend + 'static {
+        self.auth = Some(Arc::new(backend));
+        self
+    }
+
+    /// Sets whether clients are accepted without authentication, overriding
+    /// the default.
+    pub fn anonymous(mut self, allowed: bool) -> Self {
+        self.anonymous = Some(allowed);
+        self
+    }
+
+    fn allows_anonymous(&self) -> bool {
+        self.anonymous.unwrap_or(self.auth.is_none())
+    }
+
+    /// Negotiates with the client connected through `socket`, authenticates
+    /// it and reads its request.
+    ///
+    /// Username/password authentication is preferred over none when the
+    /// client offers both.
+    ///
+    /// # Error
+    ///
+    /// It fails if the client sends an invalid message, if it doesn't
+    /// authenticate, or if its request uses an unknown command or address
+    /// type. The client is told why before the error is returned.
+    pub async fn accept<S>(&self, socket: S) -> Result<Socks5Request<S>>
+    where S: AsyncSocket + Unpin {
+        self.handshake(socket, Vec::new()).await
+    }
+
+    /// Runs the handshake, `buf` holding the bytes already read from
+    /// `socket`.
+    pub(super) async fn handshake<S>(&self, mut socket: S, mut buf: Vec<u8>) -> Result<Socks5Request<S>>
+    where S: AsyncSocket + Unpin {
+        let selection = read_message(&mut socket, &mut buf, MethodSelection::decode).await?;
+        let method = if self.auth.is_some() && selection.methods.contains(&0x02) {
+            0x02
+        } else if self.allows_anonymous() && selection.methods.contains(&0x00) {
+            0x00
+        } else {
+            0xff
+        };
+        write(&mut socket, |message| MethodReply { method }.encode(message)).await?;
+
+        let identity = match (method, &self.auth) {
+            (0x02, Some(backend)) => Some(authenticate(&**backend, &mut socket, &mut buf).await?),
+            (0x00, _) => None,
+            _ => return Err(Error::NoAcceptableAuthMethods),
+        };
+
+        let request = match read_message(&mut socket, &mut buf, Request::decode).await {
+            Ok(request) => request,
+            Err(err) => {
+                let code = match err {
+                    Error::InvalidMessage(DecodeError::UnknownCommand(_)) => ReplyCode::CommandNotSupported,
+                    Error::InvalidMessage(DecodeError::UnknownAddressType(_)) => ReplyCode::AddressTypeNotSupported,
+                    _ => return Err(err),
+                };
+                write_reply(&mut socket, code, unspecified()).await?;
+                return Err(err);
+            },
+        };
+
+        Ok(Socks5Request {
+            socket,
+            command: request.command,
+            target: request.target,
+            identity,
+        })
+    }
+}
+
+/// A request read by a `Socks5Server`, to be answered by the application.
+#[derive(Debug)]
+pub struct Socks5Request<S> {
+    socket: S,
+    command: Command,
+    target: TargetAddr<'static>,
+    identity: Option<Identity>,
+}
+
+impl<S> Socks5Request<S>
+where S: AsyncSocket + Unpin
+{
+    /// Returns the command requested by the client.
+    pub fn command(&self) -> Command {
+        self.command
+    }
+
+    /// Returns the target of the command.
+    pub fn target(&self) -> &TargetAddr<'static> {
+        &self.target
+    }
+
+    /// Returns the identity the client authenticated as, if any.
+    pub fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    /// Sends a reply to the client.
+    ///
+    /// The second reply of a BIND command, carrying the address of the
+    /// remote process, is sent this way after the first one.
+    ///
+    /// # Error
+    ///
+    /// It fails if `addr` is a domain name longer than 255 bytes, or if the
+    /// reply can't be written.
+    pub async fn reply(&mut self, code: ReplyCode, addr: TargetAddr<'_>) -> Result<()> {
+        write_reply(&mut self.socket, code, addr).await
+    }
+
+    /// Tells the client the command succeeded, `addr` being the address
+    /// bound by the server, and returns the connection for relaying.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`, or fails if the reply can't be written.
+    pub async fn succeed<'t, T>(mut self, addr: T) -> Result<S>
+    where T: IntoTargetAddr<'t> {
+        self.reply(ReplyCode::Succeeded, addr.into_target_addr()?).await?;
+        Ok(self.socket)
+    }
+
+    /// Tells the client the command failed because of `err`, with the reply
+    /// the client maps back to the same error.
+    pub async fn fail(mut self, err: &Error) -> Result<()> {
+        self.reply(ReplyCode::from(err), unspecified()).await
+    }
+
+    /// Checks the request of the client connected from `client` against
+    /// `acl`, denying it with reply `0x02` if the list does.
+    ///
+    /// # Error
+    ///
+    /// It fails with `Error::ConnectionNotAllowedByRuleset` if the request is
+    /// denied, or if the reply can't be written.
+    pub async fn authorize(self, acl: &Acl, client: IpAddr) -> Result<Self> {
+        match acl.evaluate(client, self.identity(), self.command, &self.target) {
+            Action::Allow => Ok(self),
+            Action::Deny => {
+                self.fail(&Error::ConnectionNotAllowedByRuleset).await?;
+                Err(Error::ConnectionNotAllowedByRuleset)
+            },
+        }
+    }
+
+    /// Returns a mutable reference to the connection to the client.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.socket
+    }
+
+    /// Consumes the request without replying, returning the connection to
+    /// the client.
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+}
+
+/// Runs the username/password sub-negotiation (RFC 1929).
+async fn authenticate<S>(backend: &dyn AuthBackend, socket: &mut S, buf: &mut Vec<u8>) -> Result<Identity>
+where S: AsyncSocket + Unpin {
+    let request = match read_message(socket, buf, PasswordAuthRequest::decode).await {
+        Ok(request) => request,
+        // RFC 1929 allows any octets, but no user can be named by them.
+        Err(Error::InvalidMessage(DecodeError::InvalidUtf8)) => {
+            write(socket, |message| PasswordAuthResponse { status: 0x01 }.encode(message)).await?;
+            return Err(Error::PasswordAuthFailure(0x01));
+        },
+        Err(err) => return Err(err),
+    };
+    let res = backend.check_password(&request.username, &request.password).await;
+    let status = if let Ok(Some(_)) = res { 0x00 } else { 0x01 };
+    write(socket, |message| PasswordAuthResponse { status }.encode(message)).await?;
+    res?.ok_or(Error::PasswordAuthFailure(status))
+}
+
+fn unspecified() -> TargetAddr<'static> {
+    TargetAddr::Ip(SocketAddr::from(([0, 0, 0, 0], 0)))
+}
+
+async fn write<S, F>(socket: &mut S, encode: F) -> Result<()>
+where
+    S: AsyncSocket + Unpin,
+    F: FnOnce(&mut BytesMut),
+{
+    let mut message = BytesMut::new();
+    encode(&mut message);
+    socket.write_all(&message).await?;
+    Ok(())
+}
+
+async fn write_reply<S>(socket: &mut S, code: ReplyCode, addr: TargetAddr<'_>) -> Result<()>
+where S: AsyncSocket + Unpin {
+    let mut message = BytesMut::new();
+    Reply { code, addr }.encode(&mut message)?;
+    socket.write_all(&message).await?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{Command, DecodeError, Error, Identity, Result, Socks5Server};
+    use crate::{server::StaticAuth, tcp::Socks5Stream};
+
+    #[test]
+    fn password_connect() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (socket, proxy) = tokio::io::duplex(1024);
+            let server = tokio::spawn(async move {
+                let request = Socks5Server::new()
+                    .auth(StaticAuth::new().user("me", "pw"))
+                    .accept(proxy)
+                    .await?;
+                assert_eq!(request.command(), Command::Connect);
+                assert_eq!(request.target().to_string(), "example.com:80");
+                assert_eq!(request.identity().map(Identity::name), Some("me"));
+                let mut client = request.succeed("10.0.0.2:8080").await?;
+                let mut buf = [0; 5];
+                client.read_exact(&mut buf).await?;
+                client.write_all(&buf).await?;
+                Result::Ok(())
+            });
+
+            let mut stream =
+                Socks5Stream::connect_with_password_and_socket(socket, "example.com:80", "me", "pw").await?;
+            assert_eq!(stream.target_addr().to_string(), "10.0.0.2:8080");
+            stream.write_all(b"hello").await?;
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"hello");
+            server.await.unwrap()
+        })
+    }
+
+    #[test]
+    fn failures_are_replied() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (socket, proxy) = tokio::io::duplex(1024);
+            let server = tokio::spawn(async move {
+                let request = Socks5Server::new().accept(proxy).await?;
+                assert_eq!(request.identity(), None);
+                request.fail(&Error::ConnectionRefused).await
+            });
+            let res = Socks5Stream::connect_with_socket(socket, "10.0.0.1:80").await;
+            assert!(matches!(res, Err(Error::ConnectionRefused)));
+            server.await.unwrap()
+        })
+    }
+
+    #[test]
+    fn authentication_is_enforced() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let server = Socks5Server::new().auth(StaticAuth::new().user("me", "pw"));
+
+            let (socket, proxy) = tokio::io::duplex(1024);
+            let accept = tokio::spawn({
+                let server = server.clone();
+                async move { server.accept(proxy).await.map(|_| ()) }
+            });
+            let res = Socks5Stream::connect_with_socket(socket, "10.0.0.1:80").await;
+            assert!(matches!(res, Err(Error::NoAcceptableAuthMethods)));
+            assert!(matches!(accept.await.unwrap(), Err(Error::NoAcceptableAuthMethods)));
+
+            let (socket, proxy) = tokio::io::duplex(1024);
+            let accept = tokio::spawn({
+                let server = server.clone();
+                async move { server.accept(proxy).await.map(|_| ()) }
+            });
+            let res = Socks5Stream::connect_with_password_and_socket(socket, "10.0.0.1:80", "me", "bad").await;
+            assert!(matches!(res, Err(Error::PasswordAuthFailure(1))));
+            assert!(matches!(accept.await.unwrap(), Err(Error::PasswordAuthFailure(1))));
+
+            let (mut socket, proxy) = tokio::io::duplex(1024);
+            let accept = tokio::spawn(async move { server.accept(proxy).await.map(|_| ()) });
+            let mut buf = [0; 2];
+            socket.write_all(&[0x05, 1, 0x02]).await?;
+            socket.read_exact(&mut buf).await?;
+            assert_eq!(buf, [0x05, 0x02]);
+            socket.write_all(&[0x01, 2, 0xff, 0xfe, 2, b'p', b'w']).await?;
+            socket.read_exact(&mut buf).await?;
+            assert_eq!(buf, [0x01, 0x01]);
+            assert!(matches!(accept.await.unwrap(), Err(Error::PasswordAuthFailure(1))));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn unknown_command_is_not_supported() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (mut socket, proxy) = tokio::io::duplex(1024);
+            let accept = tokio::spawn(async move { Socks5Server::new().accept(proxy).await.map(|_| ()) });
+            let mut buf = [0; 10];
+            socket.write_all(&[0x05, 1, 0x00]).await?;
+            socket.read_exact(&mut buf[..2]).await?;
+            assert_eq!(buf[..2], [0x05, 0x00]);
+            socket.write_all(&[0x05, 0x09, 0x00, 0x01, 10, 0, 0, 1, 0, 80]).await?;
+            socket.read_exact(&mut buf).await?;
+            assert_eq!(buf, [0x05, 0x07, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+            assert!(matches!(
+                accept.await.unwrap(),
+                Err(Error::InvalidMessage(DecodeError::UnknownCommand(0x09)))
+            ));
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,130 @@
+//! Connection to a proxy server resolving to several addresses, following the
+//! Happy Eyeballs algorithm of RFC 8305.
+use std::{net::SocketAddr, time::Duration};
+
+use futures_util::{
+    future::{self, Either},
+    pin_mut,
+    stream::{FuturesUnordered, Stream, StreamExt},
+};
+use tokio::{net::TcpStream, time};
+
+use crate::{resolve::AddressFamily, Error, ProxyUnreachable, Result};
+
+/// The delay before starting the next connection attempt while the previous
+/// ones are still pending, as recommended by section 5 of RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Connects to the first reachable address yielded by `proxy`.
+///
+/// The addresses are arranged following `family`, then tried in turn,
+/// alternating between address families.
+/// A new attempt starts as soon as the previous one fails, or after
+/// `CONNECTION_ATTEMPT_DELAY` if it is still pending, and the first established
+/// connection wins. If none is, the error lists every failed attempt.
+pub(crate) async fn connect_to_proxy<S>(proxy: &mut S, family: AddressFamily) -> Result<TcpStream>
+where S: Stream<Item = Result<SocketAddr>> + Unpin {
+    let mut addrs = Vec::new();
+    while let Some(addr) = proxy.next().await {
+        addrs.push(addr?);
+    }
+
+    let mut addrs = interleave(family.arrange(addrs)).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut failures = ProxyUnreachable::default();
+    loop {
+        if let Some(addr) = addrs.next() {
+            attempts.push(async move { (addr, TcpStream::connect(addr).await) });
+        }
+
+        let delay = if addrs.len() == 0 {
+            Either::Left(future::pending())
+        } else {
+            Either::Right(time::sleep(CONNECTION_ATTEMPT_DELAY))
+        };
+        pin_mut!(delay);
+        match future::select(attempts.next(), delay).await {
+            Either::Left((Some((_, Ok(stream))), _)) => return Ok(stream),
+            // Start the next attempt right away.
+            Either::Left((Some((addr, Err(err))), _)) => failures.push(addr, err),
+            Either::Left((None, _)) => return Err(Error::ProxyServerUnreachable(failures)),
+            Either::Right(_) => {},
+        }
+    }
+}
+
+/// Reorders `addrs` so that address families alternate, starting with the
+/// family of the first address.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = matches!(addrs.first(), Some(addr) if addr.is_ipv6());
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        net::{Ipv4Addr, Ipv6Addr},
+    };
+
+    use futures_util::stream;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn address_families_alternate() {
+        let v4 = |port| SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let v6 = |port| SocketAddr::from((Ipv6Addr::LOCALHOST, port));
+        assert_eq!(interleave(vec![v6(1), v6(2), v6(3), v4(4)]), [
+            v6(1),
+            v4(4),
+            v6(2),
+            v6(3)
+        ]);
+        assert_eq!(interleave(vec![v4(1), v4(2), v6(3), v6(4)]), [
+            v4(1),
+            v6(3),
+            v4(2),
+            v6(4)
+        ]);
+        assert_eq!(interleave(Vec::new()), []);
+    }
+
+    #[test]
+    fn unreachable_addresses_are_skipped() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+            let refused = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?.local_addr()?;
+            let addrs = vec![Ok(refused), Ok(refused), Ok(listener.local_addr()?)];
+            let stream = connect_to_proxy(&mut stream::iter(addrs), AddressFamily::Any).await?;
+            assert_eq!(stream.peer_addr()?, listener.local_addr()?);
+
+            let addrs = vec![Ok(refused), Ok(refused)];
+            match connect_to_proxy(&mut stream::iter(addrs), AddressFamily::Any).await {
+                Err(Error::ProxyServerUnreachable(failures)) => {
+                    assert_eq!(failures.addr(), Some(refused));
+                    assert_eq!(failures.attempts().len(), 2);
+                    assert_eq!(failures.attempts()[0].1.kind(), io::ErrorKind::ConnectionRefused);
+                },
+                _ => panic!("expected the proxy to be unreachable"),
+            }
+            match connect_to_proxy(&mut stream::empty(), AddressFamily::Any).await {
+                Err(Error::ProxyServerUnreachable(failures)) => assert_eq!(failures.addr(), None),
+                _ => panic!("expected the proxy to be unreachable"),
+            }
+            Ok(())
+        })
+    }
+}
@@ -1,5 +1,23 @@
+#[cfg(feature = "tokio")]
+mod connect;
 pub mod socks4;
 pub mod socks5;
 
-pub use socks4::*;
-pub use socks5::*;
+pub use socks4::{
+    Socks4Client,
+    Socks4ClientBuilder,
+    Socks4ClientHandshake,
+    Socks4Connector,
+    Socks4Listener,
+    Socks4Step,
+    Socks4Stream,
+};
+pub use socks5::{
+    Socks5Client,
+    Socks5ClientBuilder,
+    Socks5ClientHandshake,
+    Socks5Listener,
+    Socks5Step,
+    Socks5Stream,
+    SocksConnector,
+};
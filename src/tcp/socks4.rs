@@ -1,7 +1,7 @@
 use std::{
     borrow::Borrow,
     io,
-    net::{Ipv4Addr, SocketAddr},
+    net::SocketAddr,
     ops::{Deref, DerefMut},
     pin::Pin,
     task::{Context, Poll},
@@ -12,21 +12,26 @@ use futures_util::stream::{self, Fuse, Stream, StreamExt};
 use tokio::net::TcpStream;
 
 #[cfg(feature = "tokio")]
-use crate::ToProxyAddrs;
+use crate::{tcp::connect::connect_to_proxy, ToProxyAddrs};
 use crate::{
     io::{AsyncSocket, AsyncSocketExt},
+    resolve::AddressFamily,
+    time::{Deadline, Phase, Timeouts},
     Error,
     IntoTargetAddr,
     Result,
     TargetAddr,
 };
 
-#[repr(u8)]
-#[derive(Clone, Copy)]
-enum CommandV4 {
-    Connect = 0x01,
-    Bind = 0x02,
-}
+mod client;
+mod handshake;
+pub mod wire;
+
+use self::wire::Command;
+pub use self::{
+    client::{Socks4Client, Socks4ClientBuilder},
+    handshake::{Socks4ClientHandshake, Socks4Step},
+};
 
 /// A SOCKS4 client.
 ///
@@ -51,6 +56,21 @@ impl<S> DerefMut for Socks4Stream<S> {
     }
 }
 
+impl<S> Socks4Stream<S> {
+    fn validate_userid(user_id: Option<&str>) -> Result<()> {
+        // A hardcode limit for length of userid must be enforced to avoid, buffer
+        // overflow.
+        if let Some(user_id) = user_id {
+            let user_id_len = user_id.len();
+            if !(1..=255).contains(&user_id_len) {
+                Err(Error::InvalidAuthValues("userid length should between 1 to 255"))?
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(feature = "tokio")]
 impl Socks4Stream<TcpStream> {
     /// Connects to a target server through a SOCKS4 proxy given the proxy
@@ -65,7 +85,7 @@ impl Socks4Stream<TcpStream> {
         P: ToProxyAddrs,
         T: IntoTargetAddr<'t>,
     {
-        Self::execute_command(proxy, target, None, CommandV4::Connect).await
+        Self::execute_command(proxy, target, None, Command::Connect).await
     }
 
     /// Connects to a target server through a SOCKS4 proxy using given username,
@@ -84,14 +104,14 @@ impl Socks4Stream<TcpStream> {
         P: ToProxyAddrs,
         T: IntoTargetAddr<'t>,
     {
-        Self::execute_command(proxy, target, Some(user_id), CommandV4::Connect).await
+        Self::execute_command(proxy, target, Some(user_id), Command::Connect).await
     }
 
     async fn execute_command<'a, 't, P, T>(
         proxy: P,
         target: T,
         user_id: Option<&'a str>,
-        command: CommandV4,
+        command: Command,
     ) -> Result<Socks4Stream<TcpStream>>
     where
         P: ToProxyAddrs,
@@ -123,7 +143,7 @@ where S: AsyncSocket + Unpin
     /// `TargetAddr`.
     pub async fn connect_with_socket<'t, T>(socket: S, target: T) -> Result<Socks4Stream<S>>
     where T: IntoTargetAddr<'t> {
-        Self::execute_command_with_socket(socket, target, None, CommandV4::Connect).await
+        Self::execute_command_with_socket(socket, target, None, Command::Connect).await
     }
 
     /// Connects to a target server through a SOCKS4 proxy using given username,
@@ -141,27 +161,14 @@ where S: AsyncSocket + Unpin
     where
         T: IntoTargetAddr<'t>,
     {
-        Self::execute_command_with_socket(socket, target, Some(user_id), CommandV4::Connect).await
-    }
-
-    fn validate_userid(user_id: Option<&str>) -> Result<()> {
-        // A hardcode limit for length of userid must be enforced to avoid, buffer
-        // overflow.
-        if let Some(user_id) = user_id {
-            let user_id_len = user_id.len();
-            if !(1..=255).contains(&user_id_len) {
-                Err(Error::InvalidAuthValues("userid length should between 1 to 255"))?
-            }
-        }
-
-        Ok(())
+        Self::execute_command_with_socket(socket, target, Some(user_id), Command::Connect).await
     }
 
     async fn execute_command_with_socket<'a, 't, T>(
         socket: S,
         target: T,
         user_id: Option<&'a str>,
-        command: CommandV4,
+        command: Command,
     ) -> Result<Socks4Stream<S>>
     where
         T: IntoTargetAddr<'t>,
@@ -194,135 +201,74 @@ where S: AsyncSocket + Unpin
 
 /// A `Future` which resolves to a socket to the target server through proxy.
 pub struct Socks4Connector<'a, 't, S> {
-    user_id: Option<&'a str>,
-    command: CommandV4,
+    handshake: Socks4ClientHandshake<'a, 't>,
     #[allow(dead_code)]
     proxy: Fuse<S>,
-    target: TargetAddr<'t>,
-    buf: [u8; 513],
-    ptr: usize,
-    len: usize,
+    timeouts: Option<Timeouts>,
+    #[allow(dead_code)]
+    family: AddressFamily,
 }
 
 impl<'a, 't, S> Socks4Connector<'a, 't, S>
 where S: Stream<Item = Result<SocketAddr>> + Unpin
 {
-    fn new(user_id: Option<&'a str>, command: CommandV4, proxy: Fuse<S>, target: TargetAddr<'t>) -> Self {
+    fn new(user_id: Option<&'a str>, command: Command, proxy: Fuse<S>, target: TargetAddr<'t>) -> Self {
         Socks4Connector {
-            user_id,
-            command,
+            handshake: Socks4ClientHandshake::new(command, user_id, target),
             proxy,
-            target,
-            buf: [0; 513],
-            ptr: 0,
-            len: 0,
+            timeouts: None,
+            family: AddressFamily::Any,
         }
     }
 
+    fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    fn with_address_family(mut self, family: AddressFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    fn deadline(&self, phase: Phase) -> Deadline {
+        Timeouts::deadline(self.timeouts.as_ref(), phase)
+    }
+
     #[cfg(feature = "tokio")]
     /// Connect to the proxy server, authenticate and issue the SOCKS command
     pub async fn execute(&mut self) -> Result<Socks4Stream<TcpStream>> {
-        let next_addr = self.proxy.select_next_some().await?;
-        let tcp = TcpStream::connect(next_addr)
-            .await
-            .map_err(|_| Error::ProxyServerUnreachable)?;
+        let tcp = self
+            .deadline(Phase::Connect)
+            .run(connect_to_proxy(&mut self.proxy, self.family))
+            .await?;
 
         self.execute_with_socket(tcp).await
     }
 
     pub async fn execute_with_socket<T: AsyncSocket + Unpin>(&mut self, mut socket: T) -> Result<Socks4Stream<T>> {
         // Send request address that should be proxied
-        self.prepare_send_request()?;
-        socket.write_all(&self.buf[self.ptr..self.len]).await?;
-
-        let target = self.receive_reply(&mut socket).await?;
+        let target = self.deadline(Phase::Reply).run(self.receive_reply(&mut socket)).await?;
 
         Ok(Socks4Stream { socket, target })
     }
 
-    fn prepare_send_request(&mut self) -> Result<()> {
-        self.ptr = 0;
-        self.buf[..2].copy_from_slice(&[0x04, self.command as u8]);
-        match &self.target {
-            TargetAddr::Ip(SocketAddr::V4(addr)) => {
-                self.buf[2..4].copy_from_slice(&addr.port().to_be_bytes());
-                self.buf[4..8].copy_from_slice(&addr.ip().octets());
-                self.len = 8;
-                if let Some(user_id) = self.user_id {
-                    let usr_byts = user_id.as_bytes();
-                    let user_id_len = usr_byts.len();
-                    self.len += user_id_len;
-                    self.buf[8..self.len].copy_from_slice(usr_byts);
-                }
-                self.buf[self.len] = 0; // null terminator
-                self.len += 1;
-            },
-            TargetAddr::Ip(SocketAddr::V6(_)) => {
-                return Err(Error::AddressTypeNotSupported);
-            },
-            TargetAddr::Domain(domain, port) => {
-                self.buf[2..4].copy_from_slice(&port.to_be_bytes());
-                self.buf[4..8].copy_from_slice(&[0, 0, 0, 1]);
-                self.len = 8;
-                if let Some(user_id) = self.user_id {
-                    let usr_byts = user_id.as_bytes();
-                    let user_id_len = usr_byts.len();
-                    self.len += user_id_len;
-                    self.buf[8..self.len].copy_from_slice(usr_byts);
-                }
-                self.buf[self.len] = 0; // null terminator
-                self.len += 1;
-                let domain = domain.as_bytes();
-                let domain_len = domain.len();
-                self.buf[self.len..self.len + domain_len].copy_from_slice(domain);
-                self.len += domain_len;
-                self.buf[self.len] = 0;
-                self.len += 1;
-            },
-        };
-        Ok(())
-    }
-
-    fn prepare_recv_reply(&mut self) {
-        self.ptr = 0;
-        self.len = 8;
-    }
-
+    /// Sends the command if it wasn't yet, and receives its reply.
     async fn receive_reply<T: AsyncSocket + Unpin>(&mut self, tcp: &mut T) -> Result<TargetAddr<'static>> {
-        // https://www.openssh.com/txt/socks4.protocol
-        // +----+----+----+----+----+----+----+----+
-        // | VN | CD | DSTPORT |      DSTIP        |
-        // +----+----+----+----+----+----+----+----+
-        // # of bytes:	   1    1      2              4
-        //
-        // VN is the version of the reply code and should be 0. CD is the result
-        // code with one of the following values:
-        // 90: request granted
-        // 91: request rejected or failed
-        // 92: request rejected becasue SOCKS server cannot connect to
-        // identd on the client
-        // 93: request rejected because the client program and identd
-        // report different user-ids
-
-        self.prepare_recv_reply();
-        self.ptr += tcp.read_exact(&mut self.buf[self.ptr..self.len]).await?;
-        if self.buf[0] != 0 {
-            return Err(Error::InvalidResponseVersion);
-        }
-
-        match self.buf[1] {
-            0x5A => {},                                           // request granted
-            0x5B => return Err(Error::GeneralSocksServerFailure), // connection rejected/failed
-            0x5C => return Err(Error::IdentdAuthFailure),         // cannot connect to identd on the client
-            0x5D => return Err(Error::InvalidUserIdAuthFailure),  // different user-ids
-            _ => return Err(Error::UnknownError),
+        let mut input = Vec::new();
+        loop {
+            match self.handshake.step(&input)? {
+                Socks4Step::Send(message) => {
+                    tcp.write_all(&message).await?;
+                    input.clear();
+                },
+                Socks4Step::Read(len) => {
+                    input.resize(len, 0);
+                    tcp.read_exact(&mut input).await?;
+                },
+                Socks4Step::Done(target) => return Ok(target),
+            }
         }
-
-        let port = u16::from_be_bytes([self.buf[2], self.buf[3]]);
-
-        let target = Ipv4Addr::from([self.buf[4], self.buf[5], self.buf[6], self.buf[7]]);
-
-        Ok(TargetAddr::Ip(SocketAddr::new(target.into(), port)))
     }
 }
 
@@ -388,7 +334,7 @@ impl Socks4Listener<TcpStream> {
     {
         let socket = Socks4Connector::new(
             user_id,
-            CommandV4::Bind,
+            Command::Bind,
             proxy.to_proxy_addrs().fuse(),
             target.into_target_addr()?,
         )
@@ -448,7 +394,7 @@ where S: AsyncSocket + Unpin
     {
         let socket = Socks4Connector::new(
             auth,
-            CommandV4::Bind,
+            Command::Bind,
             stream::empty().fuse(),
             target.into_target_addr()?,
         )
@@ -462,7 +408,7 @@ where S: AsyncSocket + Unpin
     ///
     /// This should be forwarded to the remote process, which should open a
     /// connection to it.
-    pub fn bind_addr(&self) -> TargetAddr {
+    pub fn bind_addr(&self) -> TargetAddr<'_> {
         self.inner.target_addr()
     }
 
@@ -473,13 +419,10 @@ where S: AsyncSocket + Unpin
     /// before this method is called.
     pub async fn accept(mut self) -> Result<Socks4Stream<S>> {
         let mut connector = Socks4Connector {
-            user_id: None,
-            command: CommandV4::Bind,
+            handshake: Socks4ClientHandshake::accept(),
             proxy: stream::empty().fuse(),
-            target: self.inner.target,
-            buf: [0; 513],
-            ptr: 0,
-            len: 0,
+            timeouts: None,
+            family: AddressFamily::Any,
         };
 
         let target = connector.receive_reply(&mut self.inner.socket).await?;
@@ -0,0 +1,347 @@
+use std::{fmt, sync::Arc};
+
+use futures_util::stream::{self, Stream, StreamExt};
+#[cfg(feature = "tokio")]
+use tokio::net::TcpStream;
+
+use super::{Command, Socks4Connector, Socks4Listener, Socks4Stream};
+use crate::{
+    io::AsyncSocket,
+    resolve::{AddressFamily, DefaultResolver, Resolver, TargetResolution},
+    time::Timeouts,
+    IntoTargetAddr,
+    Result,
+    TargetAddr,
+};
+#[cfg(feature = "tokio")]
+use crate::{
+    resolve,
+    tcp::connect::connect_to_proxy,
+    time::Phase,
+    ProxyAddrsStream,
+};
+
+/// A reusable SOCKS4 client configuration.
+///
+/// It holds the address of the proxy along with every connection option, and
+/// can be cheaply cloned and shared between tasks.
+///
+/// Domain names of targets are sent to the proxy by default, which requires
+/// the SOCKS4A extension. Plain SOCKS4 proxies need them resolved locally with
+/// `TargetResolution::Local`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "tokio")]
+/// # async fn connect() -> tokio_socks::Result<()> {
+/// use tokio_socks::{resolve::TargetResolution, tcp::Socks4Client};
+///
+/// let client = Socks4Client::builder("proxy.example.com:1080")
+///     .user_id("admin")
+///     .target_resolution(TargetResolution::Local)
+///     .build()?;
+/// let stream = client.connect("example.com:80").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Socks4Client {
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    proxy: TargetAddr<'static>,
+    user_id: Option<String>,
+    timeouts: Option<Timeouts>,
+    resolver: Arc<dyn Resolver>,
+    family: AddressFamily,
+    resolution: TargetResolution,
+}
+
+impl fmt::Debug for Socks4Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks4Client")
+            .field("proxy", &self.proxy)
+            .field("user_id", &self.user_id)
+            .field("timeouts", &self.timeouts)
+            .field("family", &self.family)
+            .field("resolution", &self.resolution)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Socks4Client {
+    /// Starts building a client of the proxy at `proxy`.
+    ///
+    /// A proxy given by domain name is resolved by the client's resolver on
+    /// each connection.
+    pub fn builder<'p, P>(proxy: P) -> Socks4ClientBuilder
+    where P: IntoTargetAddr<'p> {
+        Socks4ClientBuilder {
+            proxy: proxy.into_target_addr().map(|proxy| proxy.to_owned()),
+            user_id: None,
+            timeouts: None,
+            resolver: None,
+            family: AddressFamily::Any,
+            resolution: TargetResolution::Remote,
+        }
+    }
+
+    /// Returns the address of the proxy.
+    pub fn proxy_addr(&self) -> TargetAddr<'_> {
+        match &self.proxy {
+            TargetAddr::Ip(addr) => TargetAddr::Ip(*addr),
+            TargetAddr::Domain(domain, port) => TargetAddr::Domain((&**domain).into(), *port),
+        }
+    }
+
+    async fn resolve_target<'t, T>(&self, target: T) -> Result<TargetAddr<'t>>
+    where T: IntoTargetAddr<'t> {
+        self.resolution
+            .apply(&*self.resolver, target.into_target_addr()?, true)
+            .await
+    }
+
+    fn connector<'t, S>(&self, command: Command, proxy: S, target: TargetAddr<'t>) -> Socks4Connector<'_, 't, S>
+    where S: Stream<Item = Result<std::net::SocketAddr>> + Unpin {
+        let connector = Socks4Connector::new(self.user_id.as_deref(), command, proxy.fuse(), target)
+            .with_address_family(self.family);
+        match &self.timeouts {
+            Some(timeouts) => connector.with_timeouts(timeouts.clone()),
+            None => connector,
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    fn proxy_addrs(&self) -> ProxyAddrsStream {
+        match &self.proxy {
+            TargetAddr::Ip(addr) => ProxyAddrsStream::ready(Ok(vec![*addr])),
+            TargetAddr::Domain(host, port) => resolve::resolve_host(&*self.resolver, host, *port),
+        }
+    }
+
+    /// Establishes the TCP connection to the proxy, without any handshake.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn connect_to_proxy(&self) -> Result<TcpStream> {
+        Timeouts::deadline(self.timeouts.as_ref(), Phase::Connect)
+            .run(connect_to_proxy(&mut self.proxy_addrs(), self.family))
+            .await
+    }
+
+    /// Checks that the proxy is alive by connecting to it, then to `target`
+    /// through it if any.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn probe(&self, target: Option<TargetAddr<'_>>) -> Result<()> {
+        let socket = self.connect_to_proxy().await?;
+        match target {
+            Some(target) => self.connect_with_socket(socket, target).await.map(drop),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    async fn execute<'t, T>(&self, command: Command, target: T) -> Result<Socks4Stream<TcpStream>>
+    where T: IntoTargetAddr<'t> {
+        let target = self.resolve_target(target).await?;
+        self.connector(command, self.proxy_addrs(), target).execute().await
+    }
+
+    async fn execute_with_socket<'t, S, T>(&self, socket: S, command: Command, target: T) -> Result<Socks4Stream<S>>
+    where
+        S: AsyncSocket + Unpin,
+        T: IntoTargetAddr<'t>,
+    {
+        let target = self.resolve_target(target).await?;
+        self.connector(command, stream::empty(), target)
+            .execute_with_socket(socket)
+            .await
+    }
+
+    /// Connects to a target server through the proxy.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`.
+    #[cfg(feature = "tokio")]
+    pub async fn connect<'t, T>(&self, target: T) -> Result<Socks4Stream<TcpStream>>
+    where T: IntoTargetAddr<'t> {
+        self.execute(Command::Connect, target).await
+    }
+
+    /// Connects to a target server through the proxy given a socket to it.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`.
+    pub async fn connect_with_socket<'t, S, T>(&self, socket: S, target: T) -> Result<Socks4Stream<S>>
+    where
+        S: AsyncSocket + Unpin,
+        T: IntoTargetAddr<'t>,
+    {
+        self.execute_with_socket(socket, Command::Connect, target).await
+    }
+
+    /// Initiates a BIND request to the proxy.
+    ///
+    /// The proxy will filter incoming connections based on the value of
+    /// `target`.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`.
+    #[cfg(feature = "tokio")]
+    pub async fn bind<'t, T>(&self, target: T) -> Result<Socks4Listener<TcpStream>>
+    where T: IntoTargetAddr<'t> {
+        let inner = self.execute(Command::Bind, target).await?;
+        Ok(Socks4Listener { inner })
+    }
+
+    /// Initiates a BIND request to the proxy given a socket to it.
+    ///
+    /// The proxy will filter incoming connections based on the value of
+    /// `target`.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`.
+    pub async fn bind_with_socket<'t, S, T>(&self, socket: S, target: T) -> Result<Socks4Listener<S>>
+    where
+        S: AsyncSocket + Unpin,
+        T: IntoTargetAddr<'t>,
+    {
+        let inner = self.execute_with_socket(socket, Command::Bind, target).await?;
+        Ok(Socks4Listener { inner })
+    }
+}
+
+/// A builder of `Socks4Client`, created by `Socks4Client::builder`.
+pub struct Socks4ClientBuilder {
+    proxy: Result<TargetAddr<'static>>,
+    user_id: Option<String>,
+    timeouts: Option<Timeouts>,
+    resolver: Option<Arc<dyn Resolver>>,
+    family: AddressFamily,
+    resolution: TargetResolution,
+}
+
+impl fmt::Debug for Socks4ClientBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks4ClientBuilder")
+            .field("proxy", &self.proxy)
+            .field("user_id", &self.user_id)
+            .field("timeouts", &self.timeouts)
+            .field("family", &self.family)
+            .field("resolution", &self.resolution)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Socks4ClientBuilder {
+    /// Sets the user ID sent with each request.
+    pub fn user_id<U: Into<String>>(mut self, user_id: U) -> Self {
+        self.user_id = Some(user_id.into());
+        self
+    }
+
+    /// Sets the timeouts of each connection.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    /// Sets the resolver of domain names, used for the proxy and for the
+    /// targets resolved locally.
+    pub fn resolver<R: Resolver + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Sets which addresses of the proxy are connected to.
+    pub fn address_family(mut self, family: AddressFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    /// Sets where the domain names of target servers are resolved.
+    ///
+    /// Only the IPv4 addresses of a target resolved locally are used.
+    pub fn target_resolution(mut self, resolution: TargetResolution) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Builds the client.
+    ///
+    /// # Error
+    ///
+    /// It fails if the proxy address is invalid, or if the user ID is out of
+    /// the bounds of the protocol.
+    pub fn build(self) -> Result<Socks4Client> {
+        Socks4Stream::<()>::validate_userid(self.user_id.as_deref())?;
+        Ok(Socks4Client {
+            proxy: self.proxy?,
+            user_id: self.user_id,
+            timeouts: self.timeouts,
+            resolver: self.resolver.unwrap_or_else(|| Arc::new(DefaultResolver::default())),
+            family: self.family,
+            resolution: self.resolution,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use std::net::SocketAddr;
+
+    use futures_util::future;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{resolve::ResolveFuture, Error};
+
+    /// Resolves every name to an IPv6 and an IPv4 address.
+    struct DualStackResolver;
+
+    impl Resolver for DualStackResolver {
+        fn resolve(&self, _: &str, port: u16) -> ResolveFuture {
+            Box::pin(future::ready(Ok(vec![
+                SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port)),
+                SocketAddr::from(([10, 0, 0, 1], port)),
+            ])))
+        }
+    }
+
+    #[test]
+    fn targets_are_resolved_to_ipv4_locally() -> Result<()> {
+        let client = Socks4Client::builder("127.0.0.1:1080")
+            .user_id("me")
+            .resolver(DualStackResolver)
+            .target_resolution(TargetResolution::Local)
+            .build()?;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (socket, mut proxy) = tokio::io::duplex(1024);
+            let proxy = tokio::spawn(async move {
+                let mut buf = [0; 11];
+                proxy.read_exact(&mut buf).await?;
+                assert_eq!(buf, [0x04, 0x01, 0, 80, 10, 0, 0, 1, b'm', b'e', 0]);
+                proxy.write_all(&[0x00, 0x5a, 0, 80, 10, 0, 0, 1]).await?;
+                std::io::Result::Ok(())
+            });
+
+            let stream = client.connect_with_socket(socket, "example.com:80").await?;
+            assert_eq!(stream.target_addr().to_string(), "10.0.0.1:80");
+            proxy.await.unwrap()?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn invalid_user_id_should_fail() {
+        let res = Socks4Client::builder("127.0.0.1:1080").user_id("").build();
+        assert!(matches!(res, Err(Error::InvalidAuthValues(_))));
+    }
+}
@@ -0,0 +1,231 @@
+use std::{io, net::SocketAddr};
+
+use bytes::BytesMut;
+
+use super::{
+    wire::{Command, Reply, ReplyCode, Request},
+    Socks4Stream,
+};
+use crate::{DecodeError, Error, Result, TargetAddr};
+
+/// The next thing a `Socks4ClientHandshake` needs from the connection.
+#[derive(Debug)]
+pub enum Socks4Step {
+    /// Send these bytes to the proxy, then step again with no input.
+    Send(Vec<u8>),
+    /// Read exactly this many bytes from the proxy, then step again with them.
+    Read(usize),
+    /// The proxy replied to the command with this address. The handshake is
+    /// over.
+    Done(TargetAddr<'static>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Request,
+    Reply,
+    Done,
+}
+
+/// The client side of a SOCKS4 or SOCKS4A handshake, free of any I/O.
+///
+/// It is driven like `Socks5ClientHandshake`, by calling `step` until it
+/// returns `Socks4Step::Done`. Targets given by domain name are sent with the
+/// SOCKS4A extension.
+///
+/// # Examples
+///
+/// ```
+/// use tokio_socks::{
+///     tcp::{Socks4ClientHandshake, Socks4Step},
+///     Error,
+///     IntoTargetAddr,
+/// };
+///
+/// let target = "10.0.0.1:80".into_target_addr()?;
+/// let mut handshake = Socks4ClientHandshake::connect(None, target)?;
+/// assert!(matches!(handshake.step(&[])?, Socks4Step::Send(_)));
+/// assert!(matches!(handshake.step(&[])?, Socks4Step::Read(8)));
+/// let res = handshake.step(&[0x00, 0x5b, 0, 0, 0, 0, 0, 0]);
+/// assert!(matches!(res, Err(Error::GeneralSocksServerFailure)));
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Socks4ClientHandshake<'a, 't> {
+    user_id: Option<&'a str>,
+    command: Command,
+    target: TargetAddr<'t>,
+    state: State,
+}
+
+impl<'a, 't> Socks4ClientHandshake<'a, 't> {
+    pub(super) fn new(command: Command, user_id: Option<&'a str>, target: TargetAddr<'t>) -> Self {
+        Socks4ClientHandshake {
+            user_id,
+            command,
+            target,
+            state: State::Request,
+        }
+    }
+
+    fn checked(command: Command, user_id: Option<&'a str>, target: TargetAddr<'t>) -> Result<Self> {
+        Socks4Stream::<()>::validate_userid(user_id)?;
+        match &target {
+            TargetAddr::Ip(SocketAddr::V6(_)) => return Err(Error::AddressTypeNotSupported),
+            TargetAddr::Domain(domain, _) if domain.len() > 255 => {
+                return Err(Error::InvalidTargetAddress("overlong domain"))
+            },
+            _ => {},
+        }
+        Ok(Self::new(command, user_id, target))
+    }
+
+    /// Creates the handshake of a CONNECT command to `target`, sending
+    /// `user_id` if any.
+    ///
+    /// # Error
+    ///
+    /// It fails if the user ID or the target are out of the bounds of the
+    /// protocol, including IPv6 targets.
+    pub fn connect(user_id: Option<&'a str>, target: TargetAddr<'t>) -> Result<Self> {
+        Self::checked(Command::Connect, user_id, target)
+    }
+
+    /// Creates the handshake of a BIND command, the proxy filtering incoming
+    /// connections based on `target`. It is done once the proxy replied with
+    /// the address it listens on; the second reply is read with `accept`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the user ID or the target are out of the bounds of the
+    /// protocol, including IPv6 targets.
+    pub fn bind(user_id: Option<&'a str>, target: TargetAddr<'t>) -> Result<Self> {
+        Self::checked(Command::Bind, user_id, target)
+    }
+
+    /// Advances the handshake.
+    ///
+    /// The first call receives no input. Each following call receives the
+    /// bytes requested by `Socks4Step::Read`, or nothing after
+    /// `Socks4Step::Send`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the proxy rejects the command, if it sends an invalid
+    /// reply, or if the input doesn't have the requested length.
+    pub fn step(&mut self, input: &[u8]) -> Result<Socks4Step> {
+        match self.state {
+            State::Request => {
+                let request = self.request()?;
+                self.state = State::Reply;
+                Ok(Socks4Step::Send(request))
+            },
+            State::Reply if input.is_empty() => Ok(Socks4Step::Read(8)),
+            State::Reply if input.len() != 8 => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "unexpected handshake input length").into())
+            },
+            State::Reply => {
+                self.state = State::Done;
+                reply(input).map(Socks4Step::Done)
+            },
+            State::Done => Err(io::Error::new(io::ErrorKind::InvalidInput, "handshake is already done").into()),
+        }
+    }
+
+    fn request(&self) -> Result<Vec<u8>> {
+        let mut message = BytesMut::new();
+        Request {
+            command: self.command,
+            target: self.target.clone(),
+            user_id: self.user_id.unwrap_or_default().into(),
+        }
+        .encode(&mut message)?;
+        Ok(message.to_vec())
+    }
+}
+
+impl Socks4ClientHandshake<'static, 'static> {
+    /// Creates the handshake reading the second reply of a BIND command, sent
+    /// by the proxy once the remote process connected to it. It is done with
+    /// the address of the remote process.
+    pub fn accept() -> Self {
+        Socks4ClientHandshake {
+            user_id: None,
+            command: Command::Bind,
+            target: TargetAddr::Ip(SocketAddr::from(([0, 0, 0, 0], 0))),
+            state: State::Reply,
+        }
+    }
+}
+
+fn reply(input: &[u8]) -> Result<TargetAddr<'static>> {
+    let reply = match Reply::decode(input) {
+        Ok((reply, _)) => reply,
+        Err(DecodeError::InvalidVersion(_)) => return Err(Error::InvalidResponseVersion),
+        Err(err) => return Err(err.into()),
+    };
+    match reply.code {
+        ReplyCode::Granted => Ok(TargetAddr::Ip(reply.addr.into())),
+        ReplyCode::Rejected => Err(Error::GeneralSocksServerFailure),
+        ReplyCode::IdentdUnreachable => Err(Error::IdentdAuthFailure),
+        ReplyCode::IdentdMismatch => Err(Error::InvalidUserIdAuthFailure),
+        ReplyCode::Other(_) => Err(Error::UnknownError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntoTargetAddr;
+
+    fn reply_to(code: u8) -> Result<Socks4Step> {
+        let mut handshake = Socks4ClientHandshake::connect(None, "10.0.0.1:80".into_target_addr()?)?;
+        handshake.step(&[])?;
+        handshake.step(&[])?;
+        handshake.step(&[0x00, code, 0x1f, 0x90, 10, 0, 0, 2])
+    }
+
+    #[test]
+    fn socks4a_request() -> Result<()> {
+        let target = "example.com:80".into_target_addr()?;
+        let mut handshake = Socks4ClientHandshake::bind(Some("me"), target)?;
+        match handshake.step(&[])? {
+            Socks4Step::Send(request) => {
+                assert_eq!(request[..11], [0x04, 0x02, 0, 80, 0, 0, 0, 1, b'm', b'e', 0]);
+                assert_eq!(&request[11..], b"example.com\0");
+            },
+            step => panic!("unexpected step: {:?}", step),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reply_codes() -> Result<()> {
+        match reply_to(0x5a)? {
+            Socks4Step::Done(bound) => assert_eq!(bound.to_string(), "10.0.0.2:8080"),
+            step => panic!("unexpected step: {:?}", step),
+        }
+        assert!(matches!(reply_to(0x5b), Err(Error::GeneralSocksServerFailure)));
+        assert!(matches!(reply_to(0x5c), Err(Error::IdentdAuthFailure)));
+        assert!(matches!(reply_to(0x5d), Err(Error::InvalidUserIdAuthFailure)));
+        assert!(matches!(reply_to(0x5e), Err(Error::UnknownError)));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_handshakes_fail() -> Result<()> {
+        let res = Socks4ClientHandshake::connect(None, "[::1]:80".into_target_addr()?);
+        assert!(matches!(res, Err(Error::AddressTypeNotSupported)));
+        let res = Socks4ClientHandshake::connect(Some(""), "10.0.0.1:80".into_target_addr()?);
+        assert!(matches!(res, Err(Error::InvalidAuthValues(_))));
+
+        let mut handshake = Socks4ClientHandshake::accept();
+        assert!(matches!(handshake.step(&[])?, Socks4Step::Read(8)));
+        assert!(matches!(handshake.step(&[0x00, 0x5a]), Err(Error::Io(_))));
+        assert!(matches!(
+            handshake.step(&[0x04, 0x5a, 0, 0, 0, 0, 0, 0]),
+            Err(Error::InvalidResponseVersion)
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+//! The SOCKS4 messages, with the SOCKS4A extension for domain names.
+//!
+//! Like in `socks5::wire`, each message is appended to a buffer by `encode`,
+//! and parsed from the front of a buffer by `decode`, which also returns the
+//! number of bytes the message spans.
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    str,
+};
+
+use bytes::{BufMut, BytesMut};
+
+use crate::{DecodeError, Error, Result, TargetAddr};
+
+type DecodeResult<T> = std::result::Result<T, DecodeError>;
+
+/// The version of requests.
+const VERSION: u8 = 0x04;
+
+/// The version of replies.
+const REPLY_VERSION: u8 = 0x00;
+
+/// A SOCKS4 command.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Connect to the target.
+    Connect = 0x01,
+    /// Listen for a connection from the target.
+    Bind = 0x02,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = DecodeError;
+
+    fn try_from(code: u8) -> DecodeResult<Self> {
+        match code {
+            0x01 => Ok(Command::Connect),
+            0x02 => Ok(Command::Bind),
+            code => Err(DecodeError::UnknownCommand(code)),
+        }
+    }
+}
+
+/// The status of a SOCKS4 reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyCode {
+    /// `0x5A`: request granted.
+    Granted,
+    /// `0x5B`: request rejected or failed.
+    Rejected,
+    /// `0x5C`: request rejected because the proxy cannot connect to identd on
+    /// the client.
+    IdentdUnreachable,
+    /// `0x5D`: request rejected because the client and identd report
+    /// different user IDs.
+    IdentdMismatch,
+    /// An unassigned code.
+    Other(u8),
+}
+
+impl From<u8> for ReplyCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0x5A => ReplyCode::Granted,
+            0x5B => ReplyCode::Rejected,
+            0x5C => ReplyCode::IdentdUnreachable,
+            0x5D => ReplyCode::IdentdMismatch,
+            code => ReplyCode::Other(code),
+        }
+    }
+}
+
+impl From<ReplyCode> for u8 {
+    fn from(code: ReplyCode) -> Self {
+        match code {
+            ReplyCode::Granted => 0x5A,
+            ReplyCode::Rejected => 0x5B,
+            ReplyCode::IdentdUnreachable => 0x5C,
+            ReplyCode::IdentdMismatch => 0x5D,
+            ReplyCode::Other(code) => code,
+        }
+    }
+}
+
+impl From<&Error> for ReplyCode {
+    /// Picks the reply reporting `err` to the client, which turns it back into
+    /// the matching `Error` variant. Errors without a matching reply are
+    /// reported as rejections.
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::IdentdAuthFailure => ReplyCode::IdentdUnreachable,
+            Error::InvalidUserIdAuthFailure => ReplyCode::IdentdMismatch,
+            _ => ReplyCode::Rejected,
+        }
+    }
+}
+
+/// The command sent by the client, which opens the handshake.
+///
+/// Targets given by domain name are sent with the SOCKS4A extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request<'a> {
+    /// The command.
+    pub command: Command,
+    /// The target of the command.
+    pub target: TargetAddr<'a>,
+    /// The user ID, possibly empty.
+    pub user_id: Cow<'a, str>,
+}
+
+impl Request<'_> {
+    /// Appends the message to `dst`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the target is an IPv6 address, or if the user ID or the
+    /// domain name of the target contain a NUL byte.
+    pub fn encode(&self, dst: &mut BytesMut) -> Result<()> {
+        if self.user_id.contains('\0') {
+            return Err(Error::InvalidAuthValues("user id contains a null byte"));
+        }
+        let (ip, port, domain) = match &self.target {
+            TargetAddr::Ip(SocketAddr::V4(addr)) => (*addr.ip(), addr.port(), None),
+            TargetAddr::Ip(SocketAddr::V6(_)) => return Err(Error::AddressTypeNotSupported),
+            TargetAddr::Domain(domain, _) if domain.contains('\0') => {
+                return Err(Error::InvalidTargetAddress("domain contains a null byte"))
+            },
+            TargetAddr::Domain(domain, port) => (Ipv4Addr::new(0, 0, 0, 1), *port, Some(domain)),
+        };
+        dst.put_slice(&[VERSION, self.command as u8]);
+        dst.put_u16(port);
+        dst.put_slice(&ip.octets());
+        dst.put_slice(self.user_id.as_bytes());
+        dst.put_u8(0);
+        if let Some(domain) = domain {
+            dst.put_slice(domain.as_bytes());
+            dst.put_u8(0);
+        }
+        Ok(())
+    }
+
+    /// Decodes the message at the front of `src`.
+    pub fn decode(src: &[u8]) -> DecodeResult<(Request<'static>, usize)> {
+        need(src, 8)?;
+        if src[0] != VERSION {
+            return Err(DecodeError::InvalidVersion(src[0]));
+        }
+        let command = Command::try_from(src[1])?;
+        let port = u16::from_be_bytes([src[2], src[3]]);
+        let ip = Ipv4Addr::new(src[4], src[5], src[6], src[7]);
+        let (user_id, mut len) = nul_terminated(src, 8)?;
+        // SOCKS4A: 0.0.0.x, with x non-zero, announces a domain name.
+        let target = match ip.octets() {
+            [0, 0, 0, x] if x != 0 => {
+                let (domain, end) = nul_terminated(src, len)?;
+                len = end;
+                TargetAddr::Domain(domain.into(), port)
+            },
+            _ => TargetAddr::Ip(SocketAddrV4::new(ip, port).into()),
+        };
+        Ok((
+            Request {
+                command,
+                target,
+                user_id: user_id.into(),
+            },
+            len,
+        ))
+    }
+}
+
+/// The reply of the proxy to a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    /// The status of the command.
+    pub code: ReplyCode,
+    /// The address bound by the proxy.
+    pub addr: SocketAddrV4,
+}
+
+impl Reply {
+    /// Appends the message to `dst`.
+    pub fn encode(&self, dst: &mut BytesMut) {
+        dst.put_slice(&[REPLY_VERSION, self.code.into()]);
+        dst.put_u16(self.addr.port());
+        dst.put_slice(&self.addr.ip().octets());
+    }
+
+    /// Decodes the message at the front of `src`.
+    pub fn decode(src: &[u8]) -> DecodeResult<(Self, usize)> {
+        need(src, 8)?;
+        if src[0] != REPLY_VERSION {
+            return Err(DecodeError::InvalidVersion(src[0]));
+        }
+        let port = u16::from_be_bytes([src[2], src[3]]);
+        let ip = Ipv4Addr::new(src[4], src[5], src[6], src[7]);
+        Ok((
+            Reply {
+                code: ReplyCode::from(src[1]),
+                addr: SocketAddrV4::new(ip, port),
+            },
+            8,
+        ))
+    }
+}
+
+fn need(src: &[u8], len: usize) -> DecodeResult<()> {
+    if src.len() < len {
+        return Err(DecodeError::Incomplete(len));
+    }
+    Ok(())
+}
+
+/// Decodes the string starting at `start` in `src` and terminated by a NUL
+/// byte, returning it along with the position following the terminator.
+fn nul_terminated(src: &[u8], start: usize) -> DecodeResult<(String, usize)> {
+    let len = src[start..]
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or(DecodeError::Incomplete(src.len() + 1))?;
+    let string = str::from_utf8(&src[start..start + len]).map_err(|_| DecodeError::InvalidUtf8)?;
+    Ok((string.to_owned(), start + len + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntoTargetAddr;
+
+    #[test]
+    fn round_trips() -> Result<()> {
+        let mut buf = BytesMut::new();
+        for target in ["10.0.0.1:80", "example.com:8080"].iter() {
+            for user_id in ["", "me"].iter() {
+                buf.clear();
+                let request = Request {
+                    command: Command::Bind,
+                    target: (*target).into_target_addr()?,
+                    user_id: (*user_id).into(),
+                };
+                request.encode(&mut buf)?;
+                assert_eq!(Request::decode(&buf)?, (request, buf.len()));
+            }
+        }
+
+        buf.clear();
+        let reply = Reply {
+            code: ReplyCode::IdentdMismatch,
+            addr: SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 8080),
+        };
+        reply.encode(&mut buf);
+        assert_eq!(&buf[..], [0x00, 0x5d, 0x1f, 0x90, 10, 0, 0, 2]);
+        assert_eq!(Reply::decode(&buf)?, (reply, 8));
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_messages_are_incomplete() -> Result<()> {
+        let mut buf = BytesMut::new();
+        Request {
+            command: Command::Connect,
+            target: "example.com:80".into_target_addr()?,
+            user_id: "me".into(),
+        }
+        .encode(&mut buf)?;
+        for len in 0..buf.len() {
+            match Request::decode(&buf[..len]) {
+                Err(DecodeError::Incomplete(needed)) => assert!(needed > len && needed <= buf.len()),
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+        assert_eq!(Reply::decode(&[0x00, 0x5a]), Err(DecodeError::Incomplete(8)));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_messages_are_reported() {
+        let decode = |src: &[u8]| Request::decode(src).map(|_| ());
+        assert_eq!(
+            decode(&[0x05, 0x01, 0, 80, 10, 0, 0, 1, 0]),
+            Err(DecodeError::InvalidVersion(0x05))
+        );
+        assert_eq!(
+            decode(&[0x04, 0x03, 0, 80, 10, 0, 0, 1, 0]),
+            Err(DecodeError::UnknownCommand(0x03))
+        );
+        assert_eq!(
+            decode(&[0x04, 0x01, 0, 80, 10, 0, 0, 1, 0xff, 0]),
+            Err(DecodeError::InvalidUtf8)
+        );
+        assert_eq!(
+            Reply::decode(&[0x04, 0x5a, 0, 0, 0, 0, 0, 0]),
+            Err(DecodeError::InvalidVersion(0x04))
+        );
+    }
+
+    #[test]
+    fn out_of_bounds_messages_are_not_encoded() -> Result<()> {
+        let mut buf = BytesMut::new();
+        let request = Request {
+            command: Command::Connect,
+            target: "[::1]:80".into_target_addr()?,
+            user_id: "".into(),
+        };
+        assert!(matches!(request.encode(&mut buf), Err(Error::AddressTypeNotSupported)));
+        let request = Request {
+            command: Command::Connect,
+            target: "10.0.0.1:80".into_target_addr()?,
+            user_id: "m\0e".into(),
+        };
+        assert!(matches!(request.encode(&mut buf), Err(Error::InvalidAuthValues(_))));
+        assert!(buf.is_empty());
+        Ok(())
+    }
+}
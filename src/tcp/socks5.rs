@@ -1,43 +1,49 @@
 use std::{
     borrow::Borrow,
     io,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    net::SocketAddr,
     ops::{Deref, DerefMut},
     pin::Pin,
     task::{Context, Poll},
 };
 
-use futures_util::stream::{self, Fuse, Stream, StreamExt};
+use futures_util::{
+    ready,
+    stream::{self, Fuse, Stream, StreamExt},
+};
 #[cfg(feature = "tokio")]
 use tokio::net::TcpStream;
 
 #[cfg(feature = "tokio")]
-use crate::ToProxyAddrs;
+use crate::{tcp::connect::connect_to_proxy, ToProxyAddrs};
 use crate::{
-    io::{AsyncSocket, AsyncSocketExt},
+    io::{AsyncSocket, AsyncSocketExt, Encapsulated, EncapsulatedSocket},
+    resolve::AddressFamily,
+    time::{Deadline, Phase, Timeouts},
     Authentication, Error, IntoTargetAddr, Result, TargetAddr,
 };
 
-#[repr(u8)]
-#[derive(Clone, Copy)]
-enum Command {
-    Connect = 0x01,
-    Bind = 0x02,
-    #[allow(dead_code)]
-    Associate = 0x03,
-    #[cfg(feature = "tor")]
-    TorResolve = 0xF0,
-    #[cfg(feature = "tor")]
-    TorResolvePtr = 0xF1,
-}
+mod client;
+mod handshake;
+pub mod wire;
+
+pub(crate) use self::wire::Command;
+pub use self::{
+    client::{Socks5Client, Socks5ClientBuilder},
+    handshake::{Socks5ClientHandshake, Socks5Step},
+};
 
 /// A SOCKS5 client.
 ///
-/// For convenience, it can be dereferenced to it's inner socket.
+/// For convenience, it can be dereferenced to it's inner socket. Note that if
+/// authentication installed a protection layer, data exchanged directly over
+/// the inner socket bypasses it.
 #[derive(Debug)]
 pub struct Socks5Stream<S> {
     socket: S,
     target: TargetAddr<'static>,
+    auth_method: u8,
+    encapsulation: Option<Encapsulated>,
 }
 
 impl<S> Deref for Socks5Stream<S> {
@@ -54,11 +60,51 @@ impl<S> DerefMut for Socks5Stream<S> {
     }
 }
 
+impl<S> Socks5Stream<S> {
+    pub(crate) fn validate_auth(auths: &[Authentication<'_>]) -> Result<()> {
+        if auths.is_empty() {
+            Err(Error::InvalidAuthValues("at least one authentication method should be offered"))?
+        }
+        for auth in auths {
+            Self::validate_one_auth(auth)?;
+        }
+        Ok(())
+    }
+
+    fn validate_one_auth(auth: &Authentication<'_>) -> Result<()> {
+        match auth {
+            Authentication::Password { username, password } => {
+                let username_len = username.len();
+                if !(1..=255).contains(&username_len) {
+                    Err(Error::InvalidAuthValues("username length should between 1 to 255"))?
+                }
+                let password_len = password.len();
+                if !(1..=255).contains(&password_len) {
+                    Err(Error::InvalidAuthValues("password length should between 1 to 255"))?
+                }
+            },
+            Authentication::Custom(authenticator) => {
+                let methods = authenticator.methods();
+                if !(1..=254).contains(&methods.len()) {
+                    Err(Error::InvalidAuthValues("authenticator should offer 1 to 254 methods"))?
+                }
+                if methods.contains(&0x00) || methods.contains(&0xff) {
+                    Err(Error::InvalidAuthValues("methods 0x00 and 0xFF cannot be offered by an authenticator"))?
+                }
+            },
+            Authentication::None => {},
+        }
+        Ok(())
+    }
+}
+
 #[cfg(feature = "tokio")]
 impl Socks5Stream<TcpStream> {
     /// Connects to a target server through a SOCKS5 proxy given the proxy
     /// address.
     ///
+    /// Other options are set through `Socks5Client`.
+    ///
     /// # Error
     ///
     /// It propagates the error that occurs in the conversion from `T` to
@@ -68,7 +114,7 @@ impl Socks5Stream<TcpStream> {
         P: ToProxyAddrs,
         T: IntoTargetAddr<'t>,
     {
-        Self::execute_command(proxy, target, Authentication::None, Command::Connect).await
+        Socks5Client::resolving(proxy).await?.build()?.connect(target).await
     }
 
     /// Connects to a target server through a SOCKS5 proxy using given username,
@@ -88,13 +134,8 @@ impl Socks5Stream<TcpStream> {
         P: ToProxyAddrs,
         T: IntoTargetAddr<'t>,
     {
-        Self::execute_command(
-            proxy,
-            target,
-            Authentication::Password { username, password },
-            Command::Connect,
-        )
-        .await
+        let builder = Socks5Client::resolving(proxy).await?;
+        builder.no_auth().password(username, password).build()?.connect(target).await
     }
 
     #[cfg(feature = "tor")]
@@ -105,9 +146,7 @@ impl Socks5Stream<TcpStream> {
         P: ToProxyAddrs,
         T: IntoTargetAddr<'t>,
     {
-        let sock = Self::execute_command(proxy, target, Authentication::None, Command::TorResolve).await?;
-
-        Ok(sock.target_addr().to_owned())
+        Socks5Client::resolving(proxy).await?.build()?.tor_resolve(target).await
     }
 
     #[cfg(feature = "tor")]
@@ -119,28 +158,7 @@ impl Socks5Stream<TcpStream> {
         P: ToProxyAddrs,
         T: IntoTargetAddr<'t>,
     {
-        let sock = Self::execute_command(proxy, target, Authentication::None, Command::TorResolvePtr).await?;
-
-        Ok(sock.target_addr().to_owned())
-    }
-
-    async fn execute_command<'a, 't, P, T>(
-        proxy: P,
-        target: T,
-        auth: Authentication<'a>,
-        command: Command,
-    ) -> Result<Socks5Stream<TcpStream>>
-    where
-        P: ToProxyAddrs,
-        T: IntoTargetAddr<'t>,
-    {
-        Self::validate_auth(&auth)?;
-
-        let sock = SocksConnector::new(auth, command, proxy.to_proxy_addrs().fuse(), target.into_target_addr()?)
-            .execute()
-            .await?;
-
-        Ok(sock)
+        Socks5Client::resolving(proxy).await?.build()?.tor_resolve_ptr(target).await
     }
 }
 
@@ -150,6 +168,8 @@ where
 {
     /// Connects to a target server through a SOCKS5 proxy given a socket to it.
     ///
+    /// Other options are set through `Socks5Client`.
+    ///
     /// # Error
     ///
     /// It propagates the error that occurs in the conversion from `T` to
@@ -158,7 +178,7 @@ where
     where
         T: IntoTargetAddr<'t>,
     {
-        Self::execute_command_with_socket(socket, target, Authentication::None, Command::Connect).await
+        Socks5Client::over_socket().build()?.connect_with_socket(socket, target).await
     }
 
     /// Connects to a target server through a SOCKS5 proxy using given username,
@@ -177,30 +197,8 @@ where
     where
         T: IntoTargetAddr<'t>,
     {
-        Self::execute_command_with_socket(
-            socket,
-            target,
-            Authentication::Password { username, password },
-            Command::Connect,
-        )
-        .await
-    }
-
-    fn validate_auth(auth: &Authentication<'_>) -> Result<()> {
-        match auth {
-            Authentication::Password { username, password } => {
-                let username_len = username.len();
-                if !(1..=255).contains(&username_len) {
-                    Err(Error::InvalidAuthValues("username length should between 1 to 255"))?
-                }
-                let password_len = password.len();
-                if !(1..=255).contains(&password_len) {
-                    Err(Error::InvalidAuthValues("password length should between 1 to 255"))?
-                }
-            },
-            Authentication::None => {},
-        }
-        Ok(())
+        let client = Socks5Client::over_socket().no_auth().password(username, password).build()?;
+        client.connect_with_socket(socket, target).await
     }
 
     #[cfg(feature = "tor")]
@@ -210,9 +208,7 @@ where
     where
         T: IntoTargetAddr<'t>,
     {
-        let sock = Self::execute_command_with_socket(socket, target, Authentication::None, Command::TorResolve).await?;
-
-        Ok(sock.target_addr().to_owned())
+        Socks5Client::over_socket().build()?.tor_resolve_with_socket(socket, target).await
     }
 
     #[cfg(feature = "tor")]
@@ -223,31 +219,13 @@ where
     where
         T: IntoTargetAddr<'t>,
     {
-        let sock =
-            Self::execute_command_with_socket(socket, target, Authentication::None, Command::TorResolvePtr).await?;
-
-        Ok(sock.target_addr().to_owned())
-    }
-
-    async fn execute_command_with_socket<'a, 't, T>(
-        socket: S,
-        target: T,
-        auth: Authentication<'a>,
-        command: Command,
-    ) -> Result<Socks5Stream<S>>
-    where
-        T: IntoTargetAddr<'t>,
-    {
-        Self::validate_auth(&auth)?;
-
-        let sock = SocksConnector::new(auth, command, stream::empty().fuse(), target.into_target_addr()?)
-            .execute_with_socket(socket)
-            .await?;
-
-        Ok(sock)
+        Socks5Client::over_socket().build()?.tor_resolve_ptr_with_socket(socket, target).await
     }
 
     /// Consumes the `Socks5Stream`, returning the inner socket.
+    ///
+    /// Any protection layer installed by authentication is dropped, and data
+    /// still buffered by it is lost.
     pub fn into_inner(self) -> S {
         self.socket
     }
@@ -262,255 +240,129 @@ where
             },
         }
     }
+
+    /// Returns the authentication method selected by the proxy server.
+    pub fn auth_method(&self) -> u8 {
+        self.auth_method
+    }
 }
 
 /// A `Future` which resolves to a socket to the target server through proxy.
 pub struct SocksConnector<'a, 't, S> {
-    auth: Authentication<'a>,
-    command: Command,
+    handshake: Socks5ClientHandshake<'a, 't>,
     #[allow(dead_code)]
     proxy: Fuse<S>,
-    target: TargetAddr<'t>,
-    buf: [u8; 513],
-    ptr: usize,
-    len: usize,
+    timeouts: Option<Timeouts>,
+    #[allow(dead_code)]
+    family: AddressFamily,
 }
 
 impl<'a, 't, S> SocksConnector<'a, 't, S>
 where
     S: Stream<Item = Result<SocketAddr>> + Unpin,
 {
-    fn new(auth: Authentication<'a>, command: Command, proxy: Fuse<S>, target: TargetAddr<'t>) -> Self {
+    pub(crate) fn new(
+        auths: Vec<Authentication<'a>>,
+        command: Command,
+        proxy: Fuse<S>,
+        target: TargetAddr<'t>,
+    ) -> Self {
         SocksConnector {
-            auth,
-            command,
+            handshake: Socks5ClientHandshake::new(command, auths, target),
             proxy,
-            target,
-            buf: [0; 513],
-            ptr: 0,
-            len: 0,
+            timeouts: None,
+            family: AddressFamily::Any,
         }
     }
 
+    pub(crate) fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    pub(crate) fn with_address_family(mut self, family: AddressFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    fn deadline(&self, phase: Phase) -> Deadline {
+        Timeouts::deadline(self.timeouts.as_ref(), phase)
+    }
+
     #[cfg(feature = "tokio")]
     /// Connect to the proxy server, authenticate and issue the SOCKS command
     pub async fn execute(&mut self) -> Result<Socks5Stream<TcpStream>> {
-        let next_addr = self.proxy.select_next_some().await?;
-        let tcp = TcpStream::connect(next_addr)
-            .await
-            .map_err(|_| Error::ProxyServerUnreachable)?;
+        let tcp = self
+            .deadline(Phase::Connect)
+            .run(connect_to_proxy(&mut self.proxy, self.family))
+            .await?;
 
         self.execute_with_socket(tcp).await
     }
 
     pub async fn execute_with_socket<T: AsyncSocket + Unpin>(&mut self, mut socket: T) -> Result<Socks5Stream<T>> {
-        self.authenticate(&mut socket).await?;
+        let (auth_method, mut encapsulation) = self.deadline(Phase::Auth).run(self.authenticate(&mut socket)).await?;
 
         // Send request address that should be proxied
-        self.prepare_send_request();
-        socket.write_all(&self.buf[self.ptr..self.len]).await?;
-
-        let target = self.receive_reply(&mut socket).await?;
-
-        Ok(Socks5Stream { socket, target })
-    }
-
-    fn prepare_send_method_selection(&mut self) {
-        self.ptr = 0;
-        self.buf[0] = 0x05;
-        match self.auth {
-            Authentication::None => {
-                self.buf[1..3].copy_from_slice(&[1, 0x00]);
-                self.len = 3;
-            },
-            Authentication::Password { .. } => {
-                self.buf[1..4].copy_from_slice(&[2, 0x00, 0x02]);
-                self.len = 4;
+        let deadline = self.deadline(Phase::Reply);
+        let target = match &mut encapsulation {
+            Some(layer) => {
+                deadline
+                    .run(self.receive_reply(&mut EncapsulatedSocket { socket: &mut socket, layer }))
+                    .await?
             },
-        }
-    }
-
-    fn prepare_recv_method_selection(&mut self) {
-        self.ptr = 0;
-        self.len = 2;
-    }
-
-    fn prepare_send_password_auth(&mut self) {
-        if let Authentication::Password { username, password } = self.auth {
-            self.ptr = 0;
-            self.buf[0] = 0x01;
-            let username_bytes = username.as_bytes();
-            let username_len = username_bytes.len();
-            self.buf[1] = username_len as u8;
-            self.buf[2..(2 + username_len)].copy_from_slice(username_bytes);
-            let password_bytes = password.as_bytes();
-            let password_len = password_bytes.len();
-            self.len = 3 + username_len + password_len;
-            self.buf[2 + username_len] = password_len as u8;
-            self.buf[(3 + username_len)..self.len].copy_from_slice(password_bytes);
-        } else {
-            unreachable!()
-        }
-    }
-
-    fn prepare_recv_password_auth(&mut self) {
-        self.ptr = 0;
-        self.len = 2;
-    }
-
-    fn prepare_send_request(&mut self) {
-        self.ptr = 0;
-        self.buf[..3].copy_from_slice(&[0x05, self.command as u8, 0x00]);
-        match &self.target {
-            TargetAddr::Ip(SocketAddr::V4(addr)) => {
-                self.buf[3] = 0x01;
-                self.buf[4..8].copy_from_slice(&addr.ip().octets());
-                self.buf[8..10].copy_from_slice(&addr.port().to_be_bytes());
-                self.len = 10;
-            },
-            TargetAddr::Ip(SocketAddr::V6(addr)) => {
-                self.buf[3] = 0x04;
-                self.buf[4..20].copy_from_slice(&addr.ip().octets());
-                self.buf[20..22].copy_from_slice(&addr.port().to_be_bytes());
-                self.len = 22;
-            },
-            TargetAddr::Domain(domain, port) => {
-                self.buf[3] = 0x03;
-                let domain = domain.as_bytes();
-                let len = domain.len();
-                self.buf[4] = len as u8;
-                self.buf[5..5 + len].copy_from_slice(domain);
-                self.buf[(5 + len)..(7 + len)].copy_from_slice(&port.to_be_bytes());
-                self.len = 7 + len;
-            },
-        }
-    }
+            None => deadline.run(self.receive_reply(&mut socket)).await?,
+        };
 
-    fn prepare_recv_reply(&mut self) {
-        self.ptr = 0;
-        self.len = 4;
+        Ok(Socks5Stream {
+            socket,
+            target,
+            auth_method,
+            encapsulation,
+        })
     }
 
-    async fn password_authentication_protocol<T: AsyncSocket + Unpin>(&mut self, tcp: &mut T) -> Result<()> {
-        if let Authentication::None = self.auth {
-            return Err(Error::AuthorizationRequired);
-        }
-
-        self.prepare_send_password_auth();
-        tcp.write_all(&self.buf[self.ptr..self.len]).await?;
-
-        self.prepare_recv_password_auth();
-        tcp.read_exact(&mut self.buf[self.ptr..self.len]).await?;
-
-        if self.buf[0] != 0x01 {
-            return Err(Error::InvalidResponseVersion);
-        }
-        if self.buf[1] != 0x00 {
-            return Err(Error::PasswordAuthFailure(self.buf[1]));
+    /// Negotiates the authentication method and runs it without issuing the
+    /// command, returning the method selected by the proxy.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn negotiate_with_socket<T: AsyncSocket + Unpin>(&mut self, socket: &mut T) -> Result<u8> {
+        let (auth_method, _) = self.deadline(Phase::Auth).run(self.authenticate(socket)).await?;
+        Ok(auth_method)
+    }
+
+    /// Performs the I/O requested by the handshake until it yields an event.
+    async fn drive<T: AsyncSocket + Unpin>(&mut self, tcp: &mut T) -> Result<Socks5Step> {
+        let mut input = Vec::new();
+        loop {
+            match self.handshake.step(&input)? {
+                Socks5Step::Send(message) => {
+                    tcp.write_all(&message).await?;
+                    input.clear();
+                },
+                Socks5Step::Read(len) => {
+                    input.resize(len, 0);
+                    tcp.read_exact(&mut input).await?;
+                },
+                event => return Ok(event),
+            }
         }
-
-        Ok(())
     }
 
-    async fn authenticate<T: AsyncSocket + Unpin>(&mut self, tcp: &mut T) -> Result<()> {
-        // Write request to connect/authenticate
-        self.prepare_send_method_selection();
-        tcp.write_all(&self.buf[self.ptr..self.len]).await?;
-
-        // Receive authentication method
-        self.prepare_recv_method_selection();
-        tcp.read_exact(&mut self.buf[self.ptr..self.len]).await?;
-        if self.buf[0] != 0x05 {
-            return Err(Error::InvalidResponseVersion);
-        }
-        match self.buf[1] {
-            0x00 => {
-                // No auth
-            },
-            0x02 => {
-                self.password_authentication_protocol(tcp).await?;
-            },
-            0xff => {
-                return Err(Error::NoAcceptableAuthMethods);
-            },
-            m if m != self.auth.id() => return Err(Error::UnknownAuthMethod),
-            _ => unimplemented!(),
+    /// Negotiates the authentication method and runs it, returning the method
+    /// selected by the proxy.
+    async fn authenticate<T: AsyncSocket + Unpin>(&mut self, tcp: &mut T) -> Result<(u8, Option<Encapsulated>)> {
+        match self.drive(tcp).await? {
+            Socks5Step::Authenticated { method, layer } => Ok((method, layer.map(Encapsulated::new))),
+            _ => unreachable!(),
         }
-
-        Ok(())
     }
 
+    /// Sends the command if it wasn't yet, and receives its reply.
     async fn receive_reply<T: AsyncSocket + Unpin>(&mut self, tcp: &mut T) -> Result<TargetAddr<'static>> {
-        self.prepare_recv_reply();
-        self.ptr += tcp.read_exact(&mut self.buf[self.ptr..self.len]).await?;
-        if self.buf[0] != 0x05 {
-            return Err(Error::InvalidResponseVersion);
-        }
-        if self.buf[2] != 0x00 {
-            return Err(Error::InvalidReservedByte);
-        }
-
-        match self.buf[1] {
-            0x00 => {}, // succeeded
-            0x01 => Err(Error::GeneralSocksServerFailure)?,
-            0x02 => Err(Error::ConnectionNotAllowedByRuleset)?,
-            0x03 => Err(Error::NetworkUnreachable)?,
-            0x04 => Err(Error::HostUnreachable)?,
-            0x05 => Err(Error::ConnectionRefused)?,
-            0x06 => Err(Error::TtlExpired)?,
-            0x07 => Err(Error::CommandNotSupported)?,
-            0x08 => Err(Error::AddressTypeNotSupported)?,
-            _ => Err(Error::UnknownAuthMethod)?,
-        }
-
-        match self.buf[3] {
-            // IPv4
-            0x01 => {
-                self.len = 10;
-            },
-            // IPv6
-            0x04 => {
-                self.len = 22;
-            },
-            // Domain
-            0x03 => {
-                self.len = 5;
-                self.ptr += tcp.read_exact(&mut self.buf[self.ptr..self.len]).await?;
-                self.len += self.buf[4] as usize + 2;
-            },
-            _ => Err(Error::UnknownAddressType)?,
-        }
-
-        self.ptr += tcp.read_exact(&mut self.buf[self.ptr..self.len]).await?;
-        let target: TargetAddr<'static> = match self.buf[3] {
-            // IPv4
-            0x01 => {
-                let mut ip = [0; 4];
-                ip[..].copy_from_slice(&self.buf[4..8]);
-                let ip = Ipv4Addr::from(ip);
-                let port = u16::from_be_bytes([self.buf[8], self.buf[9]]);
-                (ip, port).into_target_addr()?
-            },
-            // IPv6
-            0x04 => {
-                let mut ip = [0; 16];
-                ip[..].copy_from_slice(&self.buf[4..20]);
-                let ip = Ipv6Addr::from(ip);
-                let port = u16::from_be_bytes([self.buf[20], self.buf[21]]);
-                (ip, port).into_target_addr()?
-            },
-            // Domain
-            0x03 => {
-                let domain_bytes = self.buf[5..(self.len - 2)].to_vec();
-                let domain = String::from_utf8(domain_bytes)
-                    .map_err(|_| Error::InvalidTargetAddress("not a valid UTF-8 string"))?;
-                let port = u16::from_be_bytes([self.buf[self.len - 2], self.buf[self.len - 1]]);
-                TargetAddr::Domain(domain.into(), port)
-            },
+        match self.drive(tcp).await? {
+            Socks5Step::Done(target) => Ok(target),
             _ => unreachable!(),
-        };
-
-        Ok(target)
+        }
     }
 }
 
@@ -540,7 +392,7 @@ impl Socks5Listener<TcpStream> {
         P: ToProxyAddrs,
         T: IntoTargetAddr<'t>,
     {
-        Self::bind_with_auth(Authentication::None, proxy, target).await
+        Socks5Client::resolving(proxy).await?.build()?.bind(target).await
     }
 
     /// Initiates a BIND request to the specified proxy using given username
@@ -563,31 +415,12 @@ impl Socks5Listener<TcpStream> {
         P: ToProxyAddrs,
         T: IntoTargetAddr<'t>,
     {
-        Self::bind_with_auth(Authentication::Password { username, password }, proxy, target).await
-    }
-
-    async fn bind_with_auth<'t, P, T>(
-        auth: Authentication<'_>,
-        proxy: P,
-        target: T,
-    ) -> Result<Socks5Listener<TcpStream>>
-    where
-        P: ToProxyAddrs,
-        T: IntoTargetAddr<'t>,
-    {
-        let socket = SocksConnector::new(
-            auth,
-            Command::Bind,
-            proxy.to_proxy_addrs().fuse(),
-            target.into_target_addr()?,
-        )
-        .execute()
-        .await?;
-
-        Ok(Socks5Listener { inner: socket })
+        let builder = Socks5Client::resolving(proxy).await?;
+        builder.no_auth().password(username, password).build()?.bind(target).await
     }
 }
 
+
 impl<S> Socks5Listener<S>
 where
     S: AsyncSocket + Unpin,
@@ -606,7 +439,7 @@ where
     where
         T: IntoTargetAddr<'t>,
     {
-        Self::bind_with_auth_and_socket(Authentication::None, socket, target).await
+        Socks5Client::over_socket().build()?.bind_with_socket(socket, target).await
     }
 
     /// Initiates a BIND request to the specified proxy using given username,
@@ -628,29 +461,15 @@ where
     where
         T: IntoTargetAddr<'t>,
     {
-        Self::bind_with_auth_and_socket(Authentication::Password { username, password }, socket, target).await
-    }
-
-    async fn bind_with_auth_and_socket<'t, T>(
-        auth: Authentication<'_>,
-        socket: S,
-        target: T,
-    ) -> Result<Socks5Listener<S>>
-    where
-        T: IntoTargetAddr<'t>,
-    {
-        let socket = SocksConnector::new(auth, Command::Bind, stream::empty().fuse(), target.into_target_addr()?)
-            .execute_with_socket(socket)
-            .await?;
-
-        Ok(Socks5Listener { inner: socket })
+        let client = Socks5Client::over_socket().no_auth().password(username, password).build()?;
+        client.bind_with_socket(socket, target).await
     }
 
     /// Returns the address of the proxy-side TCP listener.
     ///
     /// This should be forwarded to the remote process, which should open a
     /// connection to it.
-    pub fn bind_addr(&self) -> TargetAddr {
+    pub fn bind_addr(&self) -> TargetAddr<'_> {
         self.inner.target_addr()
     }
 
@@ -661,20 +480,29 @@ where
     /// before this method is called.
     pub async fn accept(mut self) -> Result<Socks5Stream<S>> {
         let mut connector = SocksConnector {
-            auth: Authentication::None,
-            command: Command::Bind,
+            handshake: Socks5ClientHandshake::accept(),
             proxy: stream::empty().fuse(),
-            target: self.inner.target,
-            buf: [0; 513],
-            ptr: 0,
-            len: 0,
+            timeouts: None,
+            family: AddressFamily::Any,
         };
 
-        let target = connector.receive_reply(&mut self.inner.socket).await?;
+        let target = match &mut self.inner.encapsulation {
+            Some(layer) => {
+                connector
+                    .receive_reply(&mut EncapsulatedSocket {
+                        socket: &mut self.inner.socket,
+                        layer,
+                    })
+                    .await?
+            },
+            None => connector.receive_reply(&mut self.inner.socket).await?,
+        };
 
         Ok(Socks5Stream {
             socket: self.inner.socket,
             target,
+            auth_method: self.inner.auth_method,
+            encapsulation: self.inner.encapsulation,
         })
     }
 }
@@ -689,7 +517,20 @@ where
         cx: &mut Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
-        tokio::io::AsyncRead::poll_read(Pin::new(&mut self.socket), cx, buf)
+        let this = &mut *self;
+        let socket = &mut this.socket;
+        match &mut this.encapsulation {
+            Some(layer) => {
+                let n = ready!(layer.poll_read(cx, buf.initialize_unfilled(), |cx, buf| {
+                    let mut buf = tokio::io::ReadBuf::new(buf);
+                    ready!(tokio::io::AsyncRead::poll_read(Pin::new(&mut *socket), cx, &mut buf))?;
+                    Poll::Ready(Ok(buf.filled().len()))
+                }))?;
+                buf.advance(n);
+                Poll::Ready(Ok(()))
+            },
+            None => tokio::io::AsyncRead::poll_read(Pin::new(socket), cx, buf),
+        }
     }
 }
 
@@ -699,14 +540,29 @@ where
     T: tokio::io::AsyncWrite + Unpin,
 {
     fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
-        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.socket), cx, buf)
+        let this = &mut *self;
+        let socket = &mut this.socket;
+        match &mut this.encapsulation {
+            Some(layer) => layer.poll_write(cx, buf, |cx, buf| {
+                tokio::io::AsyncWrite::poll_write(Pin::new(&mut *socket), cx, buf)
+            }),
+            None => tokio::io::AsyncWrite::poll_write(Pin::new(socket), cx, buf),
+        }
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.socket), cx)
+        let this = &mut *self;
+        let socket = &mut this.socket;
+        if let Some(layer) = &mut this.encapsulation {
+            ready!(layer.poll_flush(cx, |cx, buf| {
+                tokio::io::AsyncWrite::poll_write(Pin::new(&mut *socket), cx, buf)
+            }))?;
+        }
+        tokio::io::AsyncWrite::poll_flush(Pin::new(socket), cx)
     }
 
     fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(tokio::io::AsyncWrite::poll_flush(self.as_mut(), cx))?;
         tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.socket), cx)
     }
 }
@@ -717,7 +573,14 @@ where
     T: futures_io::AsyncRead + Unpin,
 {
     fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
-        futures_io::AsyncRead::poll_read(Pin::new(&mut self.socket), cx, buf)
+        let this = &mut *self;
+        let socket = &mut this.socket;
+        match &mut this.encapsulation {
+            Some(layer) => layer.poll_read(cx, buf, |cx, buf| {
+                futures_io::AsyncRead::poll_read(Pin::new(&mut *socket), cx, buf)
+            }),
+            None => futures_io::AsyncRead::poll_read(Pin::new(socket), cx, buf),
+        }
     }
 }
 
@@ -727,14 +590,29 @@ where
     T: futures_io::AsyncWrite + Unpin,
 {
     fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
-        futures_io::AsyncWrite::poll_write(Pin::new(&mut self.socket), cx, buf)
+        let this = &mut *self;
+        let socket = &mut this.socket;
+        match &mut this.encapsulation {
+            Some(layer) => layer.poll_write(cx, buf, |cx, buf| {
+                futures_io::AsyncWrite::poll_write(Pin::new(&mut *socket), cx, buf)
+            }),
+            None => futures_io::AsyncWrite::poll_write(Pin::new(socket), cx, buf),
+        }
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        futures_io::AsyncWrite::poll_flush(Pin::new(&mut self.socket), cx)
+        let this = &mut *self;
+        let socket = &mut this.socket;
+        if let Some(layer) = &mut this.encapsulation {
+            ready!(layer.poll_flush(cx, |cx, buf| {
+                futures_io::AsyncWrite::poll_write(Pin::new(&mut *socket), cx, buf)
+            }))?;
+        }
+        futures_io::AsyncWrite::poll_flush(Pin::new(socket), cx)
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(futures_io::AsyncWrite::poll_flush(self.as_mut(), cx))?;
         futures_io::AsyncWrite::poll_close(Pin::new(&mut self.socket), cx)
     }
 }
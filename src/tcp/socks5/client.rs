@@ -0,0 +1,483 @@
+use std::{fmt, net::SocketAddr, sync::Arc};
+
+use futures_util::stream::{self, StreamExt};
+#[cfg(feature = "tokio")]
+use tokio::net::TcpStream;
+
+use super::{Command, Socks5Listener, Socks5Stream, SocksConnector};
+use crate::{
+    auth::Socks5Authenticator,
+    io::AsyncSocket,
+    resolve::{AddressFamily, DefaultResolver, Resolver, TargetResolution},
+    time::Timeouts,
+    Authentication,
+    IntoTargetAddr,
+    Result,
+    TargetAddr,
+};
+#[cfg(feature = "tokio")]
+use crate::{
+    resolve,
+    tcp::connect::connect_to_proxy,
+    time::Phase,
+    ProxyAddrsStream,
+    ToProxyAddrs,
+};
+
+type AuthenticatorFactory = dyn Fn() -> Box<dyn Socks5Authenticator> + Send + Sync;
+
+/// The proxy of a client.
+#[derive(Debug, Clone)]
+enum ProxyAddr {
+    /// An address given to the builder, resolved on each connection.
+    Target(TargetAddr<'static>),
+    /// The addresses of a `ToProxyAddrs` given to an entry point of
+    /// `Socks5Stream`, resolved once. They are empty when the entry point is
+    /// given a socket to the proxy instead.
+    Resolved(Vec<SocketAddr>),
+}
+
+#[derive(Clone)]
+enum AuthConfig {
+    None,
+    Password { username: String, password: String },
+    Custom(Arc<AuthenticatorFactory>),
+}
+
+/// A reusable SOCKS5 client configuration.
+///
+/// It holds the address of the proxy along with every connection option, and
+/// can be cheaply cloned and shared between tasks.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(feature = "tokio")]
+/// # async fn connect() -> tokio_socks::Result<()> {
+/// use std::time::Duration;
+///
+/// use tokio_socks::{tcp::Socks5Client, time::Timeouts};
+///
+/// let client = Socks5Client::builder("proxy.example.com:1080")
+///     .password("admin", "123456")
+///     .no_auth()
+///     .timeouts(Timeouts::default().connect(Duration::from_secs(5)))
+///     .build()?;
+/// let stream = client.connect("example.com:80").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Socks5Client {
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    proxy: ProxyAddr,
+    auths: Vec<AuthConfig>,
+    timeouts: Option<Timeouts>,
+    resolver: Arc<dyn Resolver>,
+    family: AddressFamily,
+    resolution: TargetResolution,
+}
+
+impl fmt::Debug for Socks5Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Client")
+            .field("proxy", &self.proxy)
+            .field("timeouts", &self.timeouts)
+            .field("family", &self.family)
+            .field("resolution", &self.resolution)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Socks5Client {
+    /// Starts building a client of the proxy at `proxy`.
+    ///
+    /// A proxy given by domain name is resolved by the client's resolver on
+    /// each connection.
+    pub fn builder<'p, P>(proxy: P) -> Socks5ClientBuilder
+    where P: IntoTargetAddr<'p> {
+        Self::builder_of(proxy.into_target_addr().map(|proxy| ProxyAddr::Target(proxy.to_owned())))
+    }
+
+    /// Starts building a client of the proxy at `proxy`, resolved right away,
+    /// for the entry points of `Socks5Stream`.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn resolving<P: ToProxyAddrs>(proxy: P) -> Result<Socks5ClientBuilder> {
+        let mut addrs = Vec::new();
+        let mut proxy = proxy.to_proxy_addrs();
+        while let Some(addr) = proxy.next().await {
+            addrs.push(addr?);
+        }
+        Ok(Self::builder_of(Ok(ProxyAddr::Resolved(addrs))))
+    }
+
+    /// Starts building a client for the entry points of `Socks5Stream` given
+    /// a socket to the proxy, which never connect to it themselves.
+    pub(crate) fn over_socket() -> Socks5ClientBuilder {
+        Self::builder_of(Ok(ProxyAddr::Resolved(Vec::new())))
+    }
+
+    fn builder_of(proxy: Result<ProxyAddr>) -> Socks5ClientBuilder {
+        Socks5ClientBuilder {
+            proxy,
+            auths: Vec::new(),
+            timeouts: None,
+            resolver: None,
+            family: AddressFamily::Any,
+            resolution: TargetResolution::Remote,
+        }
+    }
+
+    /// Returns the address of the proxy.
+    pub fn proxy_addr(&self) -> TargetAddr<'_> {
+        match &self.proxy {
+            ProxyAddr::Target(TargetAddr::Ip(addr)) => TargetAddr::Ip(*addr),
+            ProxyAddr::Target(TargetAddr::Domain(domain, port)) => TargetAddr::Domain((&**domain).into(), *port),
+            ProxyAddr::Resolved(addrs) => TargetAddr::Ip(addrs.first().copied().unwrap_or_else(unspecified)),
+        }
+    }
+
+    fn authentications(&self) -> Vec<Authentication<'_>> {
+        self.auths
+            .iter()
+            .map(|auth| match auth {
+                AuthConfig::None => Authentication::None,
+                AuthConfig::Password { username, password } => Authentication::Password { username, password },
+                AuthConfig::Custom(factory) => Authentication::Custom(factory()),
+            })
+            .collect()
+    }
+
+    fn connector<'t, S>(&self, command: Command, proxy: S, target: TargetAddr<'t>) -> SocksConnector<'_, 't, S>
+    where S: futures_util::Stream<Item = Result<SocketAddr>> + Unpin {
+        let connector =
+            SocksConnector::new(self.authentications(), command, proxy.fuse(), target).with_address_family(self.family);
+        match &self.timeouts {
+            Some(timeouts) => connector.with_timeouts(timeouts.clone()),
+            None => connector,
+        }
+    }
+
+    async fn resolve_target<'t, T>(&self, target: T) -> Result<TargetAddr<'t>>
+    where T: IntoTargetAddr<'t> {
+        self.resolution
+            .apply(&*self.resolver, target.into_target_addr()?, false)
+            .await
+    }
+
+    #[cfg(feature = "tokio")]
+    fn proxy_addrs(&self) -> ProxyAddrsStream {
+        match &self.proxy {
+            ProxyAddr::Target(TargetAddr::Ip(addr)) => ProxyAddrsStream::ready(Ok(vec![*addr])),
+            ProxyAddr::Target(TargetAddr::Domain(host, port)) => resolve::resolve_host(&*self.resolver, host, *port),
+            ProxyAddr::Resolved(addrs) => ProxyAddrsStream::ready(Ok(addrs.clone())),
+        }
+    }
+
+    /// Establishes the TCP connection to the proxy, without any handshake.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn connect_to_proxy(&self) -> Result<TcpStream> {
+        Timeouts::deadline(self.timeouts.as_ref(), Phase::Connect)
+            .run(connect_to_proxy(&mut self.proxy_addrs(), self.family))
+            .await
+    }
+
+    /// Checks that the proxy is alive by negotiating the authentication
+    /// method, then connecting to `target` if any.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn probe(&self, target: Option<TargetAddr<'_>>) -> Result<()> {
+        let mut socket = self.connect_to_proxy().await?;
+        match target {
+            Some(target) => self.connect_with_socket(socket, target).await.map(drop),
+            None => {
+                // The target is never sent.
+                let target = TargetAddr::Ip(unspecified());
+                self.connector(Command::Connect, stream::empty(), target)
+                    .negotiate_with_socket(&mut socket)
+                    .await
+                    .map(drop)
+            },
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    async fn execute<'t, T>(&self, command: Command, target: T) -> Result<Socks5Stream<TcpStream>>
+    where T: IntoTargetAddr<'t> {
+        let target = self.resolve_target(target).await?;
+        self.connector(command, self.proxy_addrs(), target).execute().await
+    }
+
+    async fn execute_with_socket<'t, S, T>(&self, socket: S, command: Command, target: T) -> Result<Socks5Stream<S>>
+    where
+        S: AsyncSocket + Unpin,
+        T: IntoTargetAddr<'t>,
+    {
+        let target = self.resolve_target(target).await?;
+        self.connector(command, stream::empty(), target)
+            .execute_with_socket(socket)
+            .await
+    }
+
+    /// Connects to a target server through the proxy.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`.
+    #[cfg(feature = "tokio")]
+    pub async fn connect<'t, T>(&self, target: T) -> Result<Socks5Stream<TcpStream>>
+    where T: IntoTargetAddr<'t> {
+        self.execute(Command::Connect, target).await
+    }
+
+    /// Connects to a target server through the proxy given a socket to it.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`.
+    pub async fn connect_with_socket<'t, S, T>(&self, socket: S, target: T) -> Result<Socks5Stream<S>>
+    where
+        S: AsyncSocket + Unpin,
+        T: IntoTargetAddr<'t>,
+    {
+        self.execute_with_socket(socket, Command::Connect, target).await
+    }
+
+    /// Initiates a BIND request to the proxy.
+    ///
+    /// The proxy will filter incoming connections based on the value of
+    /// `target`.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`.
+    #[cfg(feature = "tokio")]
+    pub async fn bind<'t, T>(&self, target: T) -> Result<Socks5Listener<TcpStream>>
+    where T: IntoTargetAddr<'t> {
+        let inner = self.execute(Command::Bind, target).await?;
+        Ok(Socks5Listener { inner })
+    }
+
+    /// Initiates a BIND request to the proxy given a socket to it.
+    ///
+    /// The proxy will filter incoming connections based on the value of
+    /// `target`.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`.
+    pub async fn bind_with_socket<'t, S, T>(&self, socket: S, target: T) -> Result<Socks5Listener<S>>
+    where
+        S: AsyncSocket + Unpin,
+        T: IntoTargetAddr<'t>,
+    {
+        let inner = self.execute_with_socket(socket, Command::Bind, target).await?;
+        Ok(Socks5Listener { inner })
+    }
+
+    /// Resolves the domain name to an ip using special Tor Resolve command.
+    #[cfg(all(feature = "tokio", feature = "tor"))]
+    pub async fn tor_resolve<'t, T>(&self, target: T) -> Result<TargetAddr<'static>>
+    where T: IntoTargetAddr<'t> {
+        let sock = self.execute(Command::TorResolve, target).await?;
+        Ok(sock.target_addr().to_owned())
+    }
+
+    /// Performs a reverse DNS query on the given ip using special Tor Resolve
+    /// PTR command.
+    #[cfg(all(feature = "tokio", feature = "tor"))]
+    pub async fn tor_resolve_ptr<'t, T>(&self, target: T) -> Result<TargetAddr<'static>>
+    where T: IntoTargetAddr<'t> {
+        let sock = self.execute(Command::TorResolvePtr, target).await?;
+        Ok(sock.target_addr().to_owned())
+    }
+
+    /// Resolves the domain name to an ip using special Tor Resolve command,
+    /// given a socket to the proxy.
+    #[cfg(feature = "tor")]
+    pub async fn tor_resolve_with_socket<'t, S, T>(&self, socket: S, target: T) -> Result<TargetAddr<'static>>
+    where
+        S: AsyncSocket + Unpin,
+        T: IntoTargetAddr<'t>,
+    {
+        let sock = self.execute_with_socket(socket, Command::TorResolve, target).await?;
+        Ok(sock.target_addr().to_owned())
+    }
+
+    /// Performs a reverse DNS query on the given ip using special Tor Resolve
+    /// PTR command, given a socket to the proxy.
+    #[cfg(feature = "tor")]
+    pub async fn tor_resolve_ptr_with_socket<'t, S, T>(&self, socket: S, target: T) -> Result<TargetAddr<'static>>
+    where
+        S: AsyncSocket + Unpin,
+        T: IntoTargetAddr<'t>,
+    {
+        let sock = self.execute_with_socket(socket, Command::TorResolvePtr, target).await?;
+        Ok(sock.target_addr().to_owned())
+    }
+}
+
+fn unspecified() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 0))
+}
+
+/// A builder of `Socks5Client`, created by `Socks5Client::builder`.
+pub struct Socks5ClientBuilder {
+    proxy: Result<ProxyAddr>,
+    auths: Vec<AuthConfig>,
+    timeouts: Option<Timeouts>,
+    resolver: Option<Arc<dyn Resolver>>,
+    family: AddressFamily,
+    resolution: TargetResolution,
+}
+
+impl fmt::Debug for Socks5ClientBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5ClientBuilder")
+            .field("proxy", &self.proxy)
+            .field("timeouts", &self.timeouts)
+            .field("family", &self.family)
+            .field("resolution", &self.resolution)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Socks5ClientBuilder {
+    /// Offers no authentication (`0x00`).
+    ///
+    /// Methods are offered in the order they are added. If none is added, only
+    /// this one is offered.
+    pub fn no_auth(mut self) -> Self {
+        self.auths.push(AuthConfig::None);
+        self
+    }
+
+    /// Offers username/password authentication (`0x02`).
+    pub fn password<U, P>(mut self, username: U, password: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.auths.push(AuthConfig::Password {
+            username: username.into(),
+            password: password.into(),
+        });
+        self
+    }
+
+    /// Offers a custom authentication method. `factory` creates the
+    /// authenticator of each connection.
+    pub fn authenticator<F, A>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> A + Send + Sync + 'static,
+        A: Socks5Authenticator + 'static,
+    {
+        self.auths.push(AuthConfig::Custom(Arc::new(move || {
+            Box::new(factory()) as Box<dyn Socks5Authenticator>
+        })));
+        self
+    }
+
+    /// Sets the timeouts of each connection.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    /// Sets the resolver of domain names, used for the proxy and for the
+    /// targets resolved locally.
+    pub fn resolver<R: Resolver + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Sets which addresses of the proxy are connected to.
+    pub fn address_family(mut self, family: AddressFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    /// Sets where the domain names of target servers are resolved.
+    ///
+    /// They are sent to the proxy by default. Local resolution goes through
+    /// the resolver of the client.
+    pub fn target_resolution(mut self, resolution: TargetResolution) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Builds the client.
+    ///
+    /// # Error
+    ///
+    /// It fails if the proxy address is invalid, or if the credentials are
+    /// out of the bounds of the protocol.
+    pub fn build(mut self) -> Result<Socks5Client> {
+        if self.auths.is_empty() {
+            self.auths.push(AuthConfig::None);
+        }
+        let client = Socks5Client {
+            proxy: self.proxy?,
+            auths: self.auths,
+            timeouts: self.timeouts,
+            resolver: self.resolver.unwrap_or_else(|| Arc::new(DefaultResolver::default())),
+            family: self.family,
+            resolution: self.resolution,
+        };
+        Socks5Stream::<()>::validate_auth(&client.authentications())?;
+        Ok(client)
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn invalid_configuration_should_fail() {
+        assert!(Socks5Client::builder("no port").build().is_err());
+        assert!(matches!(
+            Socks5Client::builder("127.0.0.1:1080").password("", "pw").build(),
+            Err(Error::InvalidAuthValues(_))
+        ));
+    }
+
+    #[test]
+    fn client_is_reusable() -> Result<()> {
+        let client = Socks5Client::builder("127.0.0.1:1080")
+            .password("me", "pw")
+            .no_auth()
+            .build()?;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            for method in [0x02, 0x00] {
+                let (socket, mut proxy) = tokio::io::duplex(1024);
+                let proxy = tokio::spawn(async move {
+                    let mut buf = [0; 10];
+                    proxy.read_exact(&mut buf[..4]).await?;
+                    assert_eq!(buf[..4], [0x05, 2, 0x02, 0x00]);
+                    proxy.write_all(&[0x05, method]).await?;
+                    if method == 0x02 {
+                        proxy.read_exact(&mut buf[..7]).await?;
+                        proxy.write_all(&[0x01, 0x00]).await?;
+                    }
+                    proxy.read_exact(&mut buf).await?;
+                    proxy.write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 80]).await?;
+                    std::io::Result::Ok(())
+                });
+
+                let stream = client.connect_with_socket(socket, "127.0.0.1:80").await?;
+                assert_eq!(stream.auth_method(), method);
+                proxy.await.unwrap()?;
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,566 @@
+use std::{
+    fmt,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use bytes::BytesMut;
+
+use super::{
+    wire::{Command, MethodSelection, PasswordAuthRequest, Request},
+    Socks5Stream,
+};
+use crate::{
+    auth::{AuthStep, Encapsulation},
+    Authentication,
+    Error,
+    IntoTargetAddr,
+    Result,
+    TargetAddr,
+};
+
+/// The next thing a `Socks5ClientHandshake` needs from the connection, or
+/// an event of the handshake.
+pub enum Socks5Step {
+    /// Send these bytes to the proxy, then step again with no input.
+    Send(Vec<u8>),
+    /// Read exactly this many bytes from the proxy, then step again with them.
+    Read(usize),
+    /// The proxy selected `method` and the authentication succeeded. Step
+    /// again with no input to continue with the command.
+    ///
+    /// If a protection layer is installed, every byte sent and read from now
+    /// on must go through it.
+    Authenticated {
+        /// The authentication method selected by the proxy.
+        method: u8,
+        /// The protection layer negotiated by the method, if any.
+        layer: Option<Box<dyn Encapsulation>>,
+    },
+    /// The proxy replied to the command with this address. The handshake is
+    /// over.
+    Done(TargetAddr<'static>),
+}
+
+impl fmt::Debug for Socks5Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Socks5Step::Send(message) => f.debug_tuple("Send").field(message).finish(),
+            Socks5Step::Read(len) => f.debug_tuple("Read").field(len).finish(),
+            Socks5Step::Authenticated { method, layer } => f
+                .debug_struct("Authenticated")
+                .field("method", method)
+                .field("layer", &layer.is_some())
+                .finish(),
+            Socks5Step::Done(target) => f.debug_tuple("Done").field(target).finish(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Greeting,
+    MethodSelection,
+    PasswordStatus,
+    /// A custom sub-negotiation, with the length of the read it requested
+    /// last, if any.
+    Custom { index: usize, method: u8, read: Option<usize> },
+    Request,
+    ReplyHeader,
+    ReplyDomainLen,
+    ReplyAddr { atyp: u8, len: usize },
+    Done,
+}
+
+impl State {
+    /// Returns the number of bytes the state is waiting for, if it processes
+    /// input.
+    fn wanted(self) -> Option<usize> {
+        match self {
+            State::MethodSelection | State::PasswordStatus => Some(2),
+            State::ReplyHeader => Some(4),
+            State::ReplyDomainLen => Some(1),
+            State::ReplyAddr { len, .. } => Some(len),
+            State::Custom { read, .. } => read,
+            _ => None,
+        }
+    }
+}
+
+/// The client side of a SOCKS5 handshake, free of any I/O.
+///
+/// It is driven by calling `step` until it returns `Socks5Step::Done`,
+/// performing the I/O requested by each step on the connection to the proxy.
+/// The async connectors of this crate are built on top of it, and it can be
+/// driven the same way by blocking sockets or any other event loop.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::{
+///     io::{Read, Write},
+///     net::TcpStream,
+/// };
+///
+/// use tokio_socks::{
+///     tcp::{Socks5ClientHandshake, Socks5Step},
+///     Authentication,
+///     IntoTargetAddr,
+/// };
+///
+/// # fn main() -> tokio_socks::Result<()> {
+/// let mut socket = TcpStream::connect("127.0.0.1:1080")?;
+/// let target = "example.com:80".into_target_addr()?;
+/// let mut handshake = Socks5ClientHandshake::connect(vec![Authentication::None], target)?;
+/// let mut input = Vec::new();
+/// let bound = loop {
+///     match handshake.step(&input)? {
+///         Socks5Step::Send(message) => {
+///             socket.write_all(&message)?;
+///             input.clear();
+///         },
+///         Socks5Step::Read(len) => {
+///             input.resize(len, 0);
+///             socket.read_exact(&mut input)?;
+///         },
+///         Socks5Step::Authenticated { .. } => input.clear(),
+///         Socks5Step::Done(bound) => break bound,
+///     }
+/// };
+/// # Ok(())
+/// # }
+/// ```
+pub struct Socks5ClientHandshake<'a, 't> {
+    auths: Vec<Authentication<'a>>,
+    command: Command,
+    target: TargetAddr<'t>,
+    state: State,
+}
+
+impl fmt::Debug for Socks5ClientHandshake<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5ClientHandshake")
+            .field("target", &self.target)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, 't> Socks5ClientHandshake<'a, 't> {
+    pub(crate) fn new(command: Command, auths: Vec<Authentication<'a>>, target: TargetAddr<'t>) -> Self {
+        Socks5ClientHandshake {
+            auths,
+            command,
+            target,
+            state: State::Greeting,
+        }
+    }
+
+    fn checked(command: Command, auths: Vec<Authentication<'a>>, target: TargetAddr<'t>) -> Result<Self> {
+        Socks5Stream::<()>::validate_auth(&auths)?;
+        if let TargetAddr::Domain(domain, _) = &target {
+            if domain.len() > 255 {
+                return Err(Error::InvalidTargetAddress("overlong domain"));
+            }
+        }
+        Ok(Self::new(command, auths, target))
+    }
+
+    /// Creates the handshake of a CONNECT command to `target`, offering the
+    /// `auths` methods.
+    ///
+    /// # Error
+    ///
+    /// It fails if the authentication methods or the target are out of the
+    /// bounds of the protocol.
+    pub fn connect(auths: Vec<Authentication<'a>>, target: TargetAddr<'t>) -> Result<Self> {
+        Self::checked(Command::Connect, auths, target)
+    }
+
+    /// Creates the handshake of a BIND command, the proxy filtering incoming
+    /// connections based on `target`. It is done once the proxy replied with
+    /// the address it listens on; the second reply is read with `accept`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the authentication methods or the target are out of the
+    /// bounds of the protocol.
+    pub fn bind(auths: Vec<Authentication<'a>>, target: TargetAddr<'t>) -> Result<Self> {
+        Self::checked(Command::Bind, auths, target)
+    }
+
+    /// Creates the handshake of a UDP ASSOCIATE command, `target` being the
+    /// address the datagrams will be sent from.
+    ///
+    /// # Error
+    ///
+    /// It fails if the authentication methods or the target are out of the
+    /// bounds of the protocol.
+    pub fn associate(auths: Vec<Authentication<'a>>, target: TargetAddr<'t>) -> Result<Self> {
+        Self::checked(Command::Associate, auths, target)
+    }
+
+    /// Creates the handshake of a Tor RESOLVE command for the domain of
+    /// `target`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the authentication methods or the target are out of the
+    /// bounds of the protocol.
+    #[cfg(feature = "tor")]
+    pub fn tor_resolve(auths: Vec<Authentication<'a>>, target: TargetAddr<'t>) -> Result<Self> {
+        Self::checked(Command::TorResolve, auths, target)
+    }
+
+    /// Creates the handshake of a Tor RESOLVE_PTR command for the address of
+    /// `target`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the authentication methods or the target are out of the
+    /// bounds of the protocol.
+    #[cfg(feature = "tor")]
+    pub fn tor_resolve_ptr(auths: Vec<Authentication<'a>>, target: TargetAddr<'t>) -> Result<Self> {
+        Self::checked(Command::TorResolvePtr, auths, target)
+    }
+
+    /// Advances the handshake.
+    ///
+    /// The first call receives no input. Each following call receives the
+    /// bytes requested by `Socks5Step::Read`, or nothing after any other step.
+    ///
+    /// # Error
+    ///
+    /// It fails if the proxy rejects the authentication or the command, if it
+    /// sends an invalid message, or if the input doesn't have the requested
+    /// length.
+    pub fn step(&mut self, input: &[u8]) -> Result<Socks5Step> {
+        if let Some(len) = self.state.wanted() {
+            // Custom authenticators may request empty reads.
+            if input.is_empty() && len > 0 {
+                return Ok(Socks5Step::Read(len));
+            }
+            if input.len() != len {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "unexpected handshake input length").into());
+            }
+        }
+
+        match self.state {
+            State::Greeting => {
+                self.state = State::MethodSelection;
+                Ok(Socks5Step::Send(self.method_selection()))
+            },
+            State::MethodSelection => self.select_method(input),
+            State::PasswordStatus => {
+                if input[0] != 0x01 {
+                    return Err(Error::InvalidResponseVersion);
+                }
+                if input[1] != 0x00 {
+                    return Err(Error::PasswordAuthFailure(input[1]));
+                }
+                self.state = State::Request;
+                Ok(Socks5Step::Authenticated {
+                    method: 0x02,
+                    layer: None,
+                })
+            },
+            State::Custom { index, method, .. } => {
+                let authenticator = match &mut self.auths[index] {
+                    Authentication::Custom(authenticator) => authenticator,
+                    _ => unreachable!(),
+                };
+                match authenticator.step(method, input)? {
+                    AuthStep::Send(message) => {
+                        self.state = State::Custom {
+                            index,
+                            method,
+                            read: None,
+                        };
+                        Ok(Socks5Step::Send(message))
+                    },
+                    AuthStep::Read(len) => {
+                        self.state = State::Custom {
+                            index,
+                            method,
+                            read: Some(len),
+                        };
+                        Ok(Socks5Step::Read(len))
+                    },
+                    AuthStep::Done(layer) => {
+                        self.state = State::Request;
+                        Ok(Socks5Step::Authenticated { method, layer })
+                    },
+                }
+            },
+            State::Request => {
+                self.state = State::ReplyHeader;
+                Ok(Socks5Step::Send(self.request()?))
+            },
+            State::ReplyHeader => self.reply_header(input),
+            State::ReplyDomainLen => {
+                let len = input[0] as usize + 2;
+                self.state = State::ReplyAddr { atyp: 0x03, len };
+                Ok(Socks5Step::Read(len))
+            },
+            State::ReplyAddr { atyp, .. } => {
+                self.state = State::Done;
+                Ok(Socks5Step::Done(reply_addr(atyp, input)?))
+            },
+            State::Done => Err(io::Error::new(io::ErrorKind::InvalidInput, "handshake is already done").into()),
+        }
+    }
+
+    fn method_selection(&self) -> Vec<u8> {
+        let mut methods = Vec::new();
+        for auth in &self.auths {
+            for &method in auth.methods() {
+                if !methods.contains(&method) {
+                    methods.push(method);
+                }
+            }
+        }
+        let mut message = BytesMut::new();
+        // Methods are unique and 0xFF is never offered, so they fit.
+        let _ = MethodSelection { methods }.encode(&mut message);
+        message.to_vec()
+    }
+
+    fn select_method(&mut self, input: &[u8]) -> Result<Socks5Step> {
+        if input[0] != 0x05 {
+            return Err(Error::InvalidResponseVersion);
+        }
+        let method = input[1];
+        let index = match self.auths.iter().position(|auth| auth.methods().contains(&method)) {
+            Some(index) => index,
+            None if method == 0xff => return Err(Error::NoAcceptableAuthMethods),
+            None if method == 0x02 => return Err(Error::AuthorizationRequired),
+            None => return Err(Error::UnknownAuthMethod),
+        };
+
+        match self.auths[index] {
+            Authentication::None => {
+                self.state = State::Request;
+                Ok(Socks5Step::Authenticated { method, layer: None })
+            },
+            Authentication::Password { username, password } => {
+                self.state = State::PasswordStatus;
+                let mut message = BytesMut::new();
+                PasswordAuthRequest {
+                    username: username.into(),
+                    password: password.into(),
+                }
+                .encode(&mut message)?;
+                Ok(Socks5Step::Send(message.to_vec()))
+            },
+            Authentication::Custom(_) => {
+                self.state = State::Custom {
+                    index,
+                    method,
+                    read: None,
+                };
+                self.step(&[])
+            },
+        }
+    }
+
+    fn request(&self) -> Result<Vec<u8>> {
+        let mut message = BytesMut::new();
+        Request {
+            command: self.command,
+            target: self.target.clone(),
+        }
+        .encode(&mut message)?;
+        Ok(message.to_vec())
+    }
+
+    fn reply_header(&mut self, input: &[u8]) -> Result<Socks5Step> {
+        if input[0] != 0x05 {
+            return Err(Error::InvalidResponseVersion);
+        }
+        if input[2] != 0x00 {
+            return Err(Error::InvalidReservedByte);
+        }
+
+        match input[1] {
+            0x00 => {}, // succeeded
+            0x01 => Err(Error::GeneralSocksServerFailure)?,
+            0x02 => Err(Error::ConnectionNotAllowedByRuleset)?,
+            0x03 => Err(Error::NetworkUnreachable)?,
+            0x04 => Err(Error::HostUnreachable)?,
+            0x05 => Err(Error::ConnectionRefused)?,
+            0x06 => Err(Error::TtlExpired)?,
+            0x07 => Err(Error::CommandNotSupported)?,
+            0x08 => Err(Error::AddressTypeNotSupported)?,
+            _ => Err(Error::UnknownAuthMethod)?,
+        }
+
+        self.state = match input[3] {
+            // IPv4
+            0x01 => State::ReplyAddr { atyp: 0x01, len: 6 },
+            // IPv6
+            0x04 => State::ReplyAddr { atyp: 0x04, len: 18 },
+            // Domain
+            0x03 => State::ReplyDomainLen,
+            _ => Err(Error::UnknownAddressType)?,
+        };
+        Ok(Socks5Step::Read(self.state.wanted().unwrap_or_default()))
+    }
+}
+
+impl Socks5ClientHandshake<'static, 'static> {
+    /// Creates the handshake reading the second reply of a BIND command, sent
+    /// by the proxy once the remote process connected to it. It is done with
+    /// the address of the remote process.
+    pub fn accept() -> Self {
+        Socks5ClientHandshake {
+            auths: Vec::new(),
+            command: Command::Bind,
+            target: TargetAddr::Ip(SocketAddr::from(([0, 0, 0, 0], 0))),
+            state: State::ReplyHeader,
+        }
+    }
+}
+
+/// Parses the address of a reply, given its type and the bytes following the
+/// header, without the length of a domain name.
+fn reply_addr(atyp: u8, input: &[u8]) -> Result<TargetAddr<'static>> {
+    let (addr, port) = input.split_at(input.len() - 2);
+    let port = u16::from_be_bytes([port[0], port[1]]);
+    match atyp {
+        // IPv4
+        0x01 => {
+            let mut ip = [0; 4];
+            ip[..].copy_from_slice(addr);
+            (Ipv4Addr::from(ip), port).into_target_addr()
+        },
+        // IPv6
+        0x04 => {
+            let mut ip = [0; 16];
+            ip[..].copy_from_slice(addr);
+            (Ipv6Addr::from(ip), port).into_target_addr()
+        },
+        // Domain
+        _ => {
+            let domain = String::from_utf8(addr.to_vec())
+                .map_err(|_| Error::InvalidTargetAddress("not a valid UTF-8 string"))?;
+            Ok(TargetAddr::Domain(domain.into(), port))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expect_send(step: Result<Socks5Step>) -> Vec<u8> {
+        match step {
+            Ok(Socks5Step::Send(message)) => message,
+            step => panic!("unexpected step: {:?}", step),
+        }
+    }
+
+    fn expect_read(step: Result<Socks5Step>) -> usize {
+        match step {
+            Ok(Socks5Step::Read(len)) => len,
+            step => panic!("unexpected step: {:?}", step),
+        }
+    }
+
+    #[test]
+    fn password_handshake() -> Result<()> {
+        let auths = vec![Authentication::None, Authentication::Password {
+            username: "me",
+            password: "pw",
+        }];
+        let mut handshake = Socks5ClientHandshake::connect(auths, "example.com:80".into_target_addr()?)?;
+
+        assert_eq!(expect_send(handshake.step(&[])), [0x05, 2, 0x00, 0x02]);
+        assert_eq!(expect_read(handshake.step(&[])), 2);
+        assert_eq!(expect_send(handshake.step(&[0x05, 0x02])), [
+            0x01, 2, b'm', b'e', 2, b'p', b'w'
+        ]);
+        assert_eq!(expect_read(handshake.step(&[])), 2);
+        match handshake.step(&[0x01, 0x00])? {
+            Socks5Step::Authenticated {
+                method: 0x02,
+                layer: None,
+            } => {},
+            step => panic!("unexpected step: {:?}", step),
+        }
+
+        let request = expect_send(handshake.step(&[]));
+        assert_eq!(request[..5], [0x05, 0x01, 0x00, 0x03, 11]);
+        assert_eq!(&request[5..16], b"example.com");
+        assert_eq!(request[16..], [0, 80]);
+        assert_eq!(expect_read(handshake.step(&[])), 4);
+        assert_eq!(expect_read(handshake.step(&[0x05, 0x00, 0x00, 0x03])), 1);
+        assert_eq!(expect_read(handshake.step(&[4])), 6);
+        match handshake.step(b"host\x01\xbb")? {
+            Socks5Step::Done(bound) => assert_eq!(bound.to_string(), "host:443"),
+            step => panic!("unexpected step: {:?}", step),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejections_and_invalid_input_fail() -> Result<()> {
+        let mut handshake =
+            Socks5ClientHandshake::connect(vec![Authentication::None], "10.0.0.1:80".into_target_addr()?)?;
+        expect_send(handshake.step(&[]));
+        assert!(matches!(handshake.step(&[0x05]), Err(Error::Io(_))));
+        match handshake.step(&[0x05, 0x00])? {
+            Socks5Step::Authenticated { method: 0x00, .. } => {},
+            step => panic!("unexpected step: {:?}", step),
+        }
+        expect_send(handshake.step(&[]));
+        let res = handshake.step(&[0x05, 0x02, 0x00, 0x01]);
+        assert!(matches!(res, Err(Error::ConnectionNotAllowedByRuleset)));
+
+        let res = Socks5ClientHandshake::connect(Vec::new(), "10.0.0.1:80".into_target_addr()?);
+        assert!(matches!(res, Err(Error::InvalidAuthValues(_))));
+        Ok(())
+    }
+
+    /// A method succeeding once it read a 3-byte token.
+    struct TokenAuthenticator;
+
+    impl crate::auth::Socks5Authenticator for TokenAuthenticator {
+        fn methods(&self) -> &[u8] {
+            &[0x80]
+        }
+
+        fn step(&mut self, _method: u8, input: &[u8]) -> Result<AuthStep> {
+            match input.len() {
+                0 => Ok(AuthStep::Read(3)),
+                _ => Ok(AuthStep::Done(None)),
+            }
+        }
+    }
+
+    #[test]
+    fn custom_input_length_is_checked() -> Result<()> {
+        let auths = vec![Authentication::Custom(Box::new(TokenAuthenticator))];
+        let mut handshake = Socks5ClientHandshake::connect(auths, "10.0.0.1:80".into_target_addr()?)?;
+        expect_send(handshake.step(&[]));
+        assert_eq!(expect_read(handshake.step(&[0x05, 0x80])), 3);
+        assert_eq!(expect_read(handshake.step(&[])), 3);
+        assert!(matches!(handshake.step(&[1, 2]), Err(Error::Io(_))));
+        match handshake.step(&[1, 2, 3])? {
+            Socks5Step::Authenticated { method: 0x80, .. } => {},
+            step => panic!("unexpected step: {:?}", step),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn accept_reads_the_second_reply() -> Result<()> {
+        let mut handshake = Socks5ClientHandshake::accept();
+        assert_eq!(expect_read(handshake.step(&[])), 4);
+        assert_eq!(expect_read(handshake.step(&[0x05, 0x00, 0x00, 0x01])), 6);
+        match handshake.step(&[10, 0, 0, 2, 0x1f, 0x90])? {
+            Socks5Step::Done(peer) => assert_eq!(peer.to_string(), "10.0.0.2:8080"),
+            step => panic!("unexpected step: {:?}", step),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,580 @@
+//! The SOCKS5 messages of RFC 1928 and RFC 1929.
+//!
+//! Each message is appended to a buffer by `encode`, and parsed from the
+//! front of a buffer by `decode`, which also returns the number of bytes the
+//! message spans. Decoding a truncated message fails with
+//! `DecodeError::Incomplete`, so that more bytes can be read and decoding
+//! attempted again.
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    str,
+};
+
+use bytes::{BufMut, BytesMut};
+
+use crate::{DecodeError, Error, Result, TargetAddr};
+
+type DecodeResult<T> = std::result::Result<T, DecodeError>;
+
+/// The version of the protocol.
+const VERSION: u8 = 0x05;
+
+/// The version of the username/password sub-negotiation.
+const PASSWORD_AUTH_VERSION: u8 = 0x01;
+
+/// A SOCKS5 command.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Connect to the target.
+    Connect = 0x01,
+    /// Listen for a connection from the target.
+    Bind = 0x02,
+    /// Relay UDP datagrams.
+    Associate = 0x03,
+    /// Resolve a domain name, a Tor extension.
+    TorResolve = 0xF0,
+    /// Resolve an IP address to a domain name, a Tor extension.
+    TorResolvePtr = 0xF1,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = DecodeError;
+
+    fn try_from(code: u8) -> DecodeResult<Self> {
+        match code {
+            0x01 => Ok(Command::Connect),
+            0x02 => Ok(Command::Bind),
+            0x03 => Ok(Command::Associate),
+            0xF0 => Ok(Command::TorResolve),
+            0xF1 => Ok(Command::TorResolvePtr),
+            code => Err(DecodeError::UnknownCommand(code)),
+        }
+    }
+}
+
+/// The status of a SOCKS5 reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyCode {
+    /// `0x00`: the command succeeded.
+    Succeeded,
+    /// `0x01`: general SOCKS server failure.
+    GeneralFailure,
+    /// `0x02`: connection not allowed by ruleset.
+    ConnectionNotAllowed,
+    /// `0x03`: network unreachable.
+    NetworkUnreachable,
+    /// `0x04`: host unreachable.
+    HostUnreachable,
+    /// `0x05`: connection refused.
+    ConnectionRefused,
+    /// `0x06`: TTL expired.
+    TtlExpired,
+    /// `0x07`: command not supported.
+    CommandNotSupported,
+    /// `0x08`: address type not supported.
+    AddressTypeNotSupported,
+    /// An unassigned code.
+    Other(u8),
+}
+
+impl From<u8> for ReplyCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0x00 => ReplyCode::Succeeded,
+            0x01 => ReplyCode::GeneralFailure,
+            0x02 => ReplyCode::ConnectionNotAllowed,
+            0x03 => ReplyCode::NetworkUnreachable,
+            0x04 => ReplyCode::HostUnreachable,
+            0x05 => ReplyCode::ConnectionRefused,
+            0x06 => ReplyCode::TtlExpired,
+            0x07 => ReplyCode::CommandNotSupported,
+            0x08 => ReplyCode::AddressTypeNotSupported,
+            code => ReplyCode::Other(code),
+        }
+    }
+}
+
+impl From<ReplyCode> for u8 {
+    fn from(code: ReplyCode) -> Self {
+        match code {
+            ReplyCode::Succeeded => 0x00,
+            ReplyCode::GeneralFailure => 0x01,
+            ReplyCode::ConnectionNotAllowed => 0x02,
+            ReplyCode::NetworkUnreachable => 0x03,
+            ReplyCode::HostUnreachable => 0x04,
+            ReplyCode::ConnectionRefused => 0x05,
+            ReplyCode::TtlExpired => 0x06,
+            ReplyCode::CommandNotSupported => 0x07,
+            ReplyCode::AddressTypeNotSupported => 0x08,
+            ReplyCode::Other(code) => code,
+        }
+    }
+}
+
+impl From<&Error> for ReplyCode {
+    /// Picks the reply reporting `err` to the client, which turns it back into
+    /// the matching `Error` variant. Errors without a matching reply are
+    /// reported as general failures.
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::ConnectionNotAllowedByRuleset => ReplyCode::ConnectionNotAllowed,
+            Error::NetworkUnreachable => ReplyCode::NetworkUnreachable,
+            Error::HostUnreachable => ReplyCode::HostUnreachable,
+            Error::ConnectionRefused => ReplyCode::ConnectionRefused,
+            Error::TtlExpired => ReplyCode::TtlExpired,
+            Error::CommandNotSupported => ReplyCode::CommandNotSupported,
+            Error::AddressTypeNotSupported => ReplyCode::AddressTypeNotSupported,
+            Error::Io(err) => match err.kind() {
+                io::ErrorKind::ConnectionRefused => ReplyCode::ConnectionRefused,
+                io::ErrorKind::NetworkUnreachable => ReplyCode::NetworkUnreachable,
+                io::ErrorKind::HostUnreachable | io::ErrorKind::TimedOut => ReplyCode::HostUnreachable,
+                _ => ReplyCode::GeneralFailure,
+            },
+            _ => ReplyCode::GeneralFailure,
+        }
+    }
+}
+
+/// The methods offered by the client, which opens the handshake with them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodSelection {
+    /// The offered authentication methods.
+    pub methods: Vec<u8>,
+}
+
+impl MethodSelection {
+    /// Appends the message to `dst`.
+    ///
+    /// # Error
+    ///
+    /// It fails if more than 255 methods are offered.
+    pub fn encode(&self, dst: &mut BytesMut) -> Result<()> {
+        let len = u8::try_from(self.methods.len())
+            .map_err(|_| Error::InvalidAuthValues("at most 255 methods can be offered"))?;
+        dst.put_slice(&[VERSION, len]);
+        dst.put_slice(&self.methods);
+        Ok(())
+    }
+
+    /// Decodes the message at the front of `src`.
+    pub fn decode(src: &[u8]) -> DecodeResult<(Self, usize)> {
+        need(src, 2)?;
+        check_version(src[0], VERSION)?;
+        let len = 2 + src[1] as usize;
+        need(src, len)?;
+        let methods = src[2..len].to_vec();
+        Ok((MethodSelection { methods }, len))
+    }
+}
+
+/// The method selected by the proxy, `0xFF` if none is acceptable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MethodReply {
+    /// The selected authentication method.
+    pub method: u8,
+}
+
+impl MethodReply {
+    /// Appends the message to `dst`.
+    pub fn encode(&self, dst: &mut BytesMut) {
+        dst.put_slice(&[VERSION, self.method]);
+    }
+
+    /// Decodes the message at the front of `src`.
+    pub fn decode(src: &[u8]) -> DecodeResult<(Self, usize)> {
+        need(src, 2)?;
+        check_version(src[0], VERSION)?;
+        Ok((MethodReply { method: src[1] }, 2))
+    }
+}
+
+/// The credentials sent by the client for username/password authentication
+/// (RFC 1929).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordAuthRequest<'a> {
+    /// The username.
+    pub username: Cow<'a, str>,
+    /// The password.
+    pub password: Cow<'a, str>,
+}
+
+impl PasswordAuthRequest<'_> {
+    /// Appends the message to `dst`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the username or the password is empty or longer than 255
+    /// bytes.
+    pub fn encode(&self, dst: &mut BytesMut) -> Result<()> {
+        if !(1..=255).contains(&self.username.len()) {
+            return Err(Error::InvalidAuthValues("username length should between 1 to 255"));
+        }
+        if !(1..=255).contains(&self.password.len()) {
+            return Err(Error::InvalidAuthValues("password length should between 1 to 255"));
+        }
+        dst.put_slice(&[PASSWORD_AUTH_VERSION, self.username.len() as u8]);
+        dst.put_slice(self.username.as_bytes());
+        dst.put_u8(self.password.len() as u8);
+        dst.put_slice(self.password.as_bytes());
+        Ok(())
+    }
+
+    /// Decodes the message at the front of `src`.
+    pub fn decode(src: &[u8]) -> DecodeResult<(PasswordAuthRequest<'static>, usize)> {
+        need(src, 2)?;
+        check_version(src[0], PASSWORD_AUTH_VERSION)?;
+        let username_end = 2 + src[1] as usize;
+        need(src, username_end + 1)?;
+        let len = username_end + 1 + src[username_end] as usize;
+        need(src, len)?;
+        Ok((
+            PasswordAuthRequest {
+                username: utf8(&src[2..username_end])?.into(),
+                password: utf8(&src[username_end + 1..len])?.into(),
+            },
+            len,
+        ))
+    }
+}
+
+/// The status of username/password authentication, `0x00` on success.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordAuthResponse {
+    /// The status of the authentication.
+    pub status: u8,
+}
+
+impl PasswordAuthResponse {
+    /// Appends the message to `dst`.
+    pub fn encode(&self, dst: &mut BytesMut) {
+        dst.put_slice(&[PASSWORD_AUTH_VERSION, self.status]);
+    }
+
+    /// Decodes the message at the front of `src`.
+    pub fn decode(src: &[u8]) -> DecodeResult<(Self, usize)> {
+        need(src, 2)?;
+        check_version(src[0], PASSWORD_AUTH_VERSION)?;
+        Ok((PasswordAuthResponse { status: src[1] }, 2))
+    }
+}
+
+/// The command sent by the client once authenticated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request<'a> {
+    /// The command.
+    pub command: Command,
+    /// The target of the command.
+    pub target: TargetAddr<'a>,
+}
+
+impl Request<'_> {
+    /// Appends the message to `dst`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the target is a domain name longer than 255 bytes.
+    pub fn encode(&self, dst: &mut BytesMut) -> Result<()> {
+        check_addr(&self.target)?;
+        dst.put_slice(&[VERSION, self.command as u8, 0x00]);
+        encode_addr(&self.target, dst);
+        Ok(())
+    }
+
+    /// Decodes the message at the front of `src`.
+    pub fn decode(src: &[u8]) -> DecodeResult<(Request<'static>, usize)> {
+        need(src, 3)?;
+        check_version(src[0], VERSION)?;
+        check_reserved(src[2])?;
+        let command = Command::try_from(src[1])?;
+        let (target, len) = decode_addr(src, 3)?;
+        Ok((Request { command, target }, len))
+    }
+}
+
+/// The reply of the proxy to a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply<'a> {
+    /// The status of the command.
+    pub code: ReplyCode,
+    /// The address bound by the proxy, or the result of a Tor resolution.
+    pub addr: TargetAddr<'a>,
+}
+
+impl Reply<'_> {
+    /// Appends the message to `dst`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the address is a domain name longer than 255 bytes.
+    pub fn encode(&self, dst: &mut BytesMut) -> Result<()> {
+        check_addr(&self.addr)?;
+        dst.put_slice(&[VERSION, self.code.into(), 0x00]);
+        encode_addr(&self.addr, dst);
+        Ok(())
+    }
+
+    /// Decodes the message at the front of `src`.
+    pub fn decode(src: &[u8]) -> DecodeResult<(Reply<'static>, usize)> {
+        need(src, 3)?;
+        check_version(src[0], VERSION)?;
+        check_reserved(src[2])?;
+        let code = ReplyCode::from(src[1]);
+        let (addr, len) = decode_addr(src, 3)?;
+        Ok((Reply { code, addr }, len))
+    }
+}
+
+/// The header of every datagram relayed by a UDP association.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UdpHeader<'a> {
+    /// The position of the datagram in a sequence of fragments, `0` if it is
+    /// whole.
+    pub frag: u8,
+    /// The destination of the datagram, or its source when relayed back.
+    pub target: TargetAddr<'a>,
+}
+
+impl UdpHeader<'_> {
+    /// Appends the header to `dst`.
+    ///
+    /// # Error
+    ///
+    /// It fails if the target is a domain name longer than 255 bytes.
+    pub fn encode(&self, dst: &mut BytesMut) -> Result<()> {
+        check_addr(&self.target)?;
+        dst.put_slice(&[0x00, 0x00, self.frag]);
+        encode_addr(&self.target, dst);
+        Ok(())
+    }
+
+    /// Decodes the header at the front of `src`, the payload following it.
+    pub fn decode(src: &[u8]) -> DecodeResult<(UdpHeader<'static>, usize)> {
+        need(src, 3)?;
+        check_reserved(src[0])?;
+        check_reserved(src[1])?;
+        let (target, len) = decode_addr(src, 3)?;
+        Ok((UdpHeader { frag: src[2], target }, len))
+    }
+}
+
+fn need(src: &[u8], len: usize) -> DecodeResult<()> {
+    if src.len() < len {
+        return Err(DecodeError::Incomplete(len));
+    }
+    Ok(())
+}
+
+fn check_version(version: u8, expected: u8) -> DecodeResult<()> {
+    if version != expected {
+        return Err(DecodeError::InvalidVersion(version));
+    }
+    Ok(())
+}
+
+fn check_reserved(byte: u8) -> DecodeResult<()> {
+    if byte != 0x00 {
+        return Err(DecodeError::InvalidReservedByte(byte));
+    }
+    Ok(())
+}
+
+fn utf8(bytes: &[u8]) -> DecodeResult<String> {
+    str::from_utf8(bytes)
+        .map(str::to_owned)
+        .map_err(|_| DecodeError::InvalidUtf8)
+}
+
+fn check_addr(addr: &TargetAddr<'_>) -> Result<()> {
+    match addr {
+        TargetAddr::Domain(domain, _) if domain.len() > 255 => Err(Error::InvalidTargetAddress("overlong domain")),
+        _ => Ok(()),
+    }
+}
+
+/// Appends the address type, the address and the port of `addr`, which
+/// passed `check_addr`.
+fn encode_addr(addr: &TargetAddr<'_>, dst: &mut BytesMut) {
+    match addr {
+        TargetAddr::Ip(SocketAddr::V4(addr)) => {
+            dst.put_u8(0x01);
+            dst.put_slice(&addr.ip().octets());
+            dst.put_u16(addr.port());
+        },
+        TargetAddr::Ip(SocketAddr::V6(addr)) => {
+            dst.put_u8(0x04);
+            dst.put_slice(&addr.ip().octets());
+            dst.put_u16(addr.port());
+        },
+        TargetAddr::Domain(domain, port) => {
+            dst.put_slice(&[0x03, domain.len() as u8]);
+            dst.put_slice(domain.as_bytes());
+            dst.put_u16(*port);
+        },
+    }
+}
+
+/// Decodes the address starting at `start` in `src`, returning it along with
+/// the end of the message.
+fn decode_addr(src: &[u8], start: usize) -> DecodeResult<(TargetAddr<'static>, usize)> {
+    need(src, start + 1)?;
+    let addr = start + 1;
+    let (host_end, ip) = match src[start] {
+        0x01 => (addr + 4, true),
+        0x04 => (addr + 16, true),
+        0x03 => {
+            need(src, addr + 1)?;
+            (addr + 1 + src[addr] as usize, false)
+        },
+        atyp => return Err(DecodeError::UnknownAddressType(atyp)),
+    };
+    let len = host_end + 2;
+    need(src, len)?;
+    let port = u16::from_be_bytes([src[host_end], src[host_end + 1]]);
+    let target = match (ip, host_end - addr) {
+        (true, 4) => {
+            let mut ip = [0; 4];
+            ip.copy_from_slice(&src[addr..host_end]);
+            TargetAddr::Ip((Ipv4Addr::from(ip), port).into())
+        },
+        (true, _) => {
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&src[addr..host_end]);
+            TargetAddr::Ip((Ipv6Addr::from(ip), port).into())
+        },
+        (false, _) => TargetAddr::Domain(utf8(&src[addr + 1..host_end])?.into(), port),
+    };
+    Ok((target, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntoTargetAddr;
+
+    fn targets() -> Result<Vec<TargetAddr<'static>>> {
+        Ok(vec![
+            "10.0.0.1:80".into_target_addr()?,
+            "[2001:db8::1]:443".into_target_addr()?,
+            "example.com:8080".into_target_addr()?.to_owned(),
+        ])
+    }
+
+    #[test]
+    fn round_trips() -> Result<()> {
+        let mut buf = BytesMut::new();
+        let selection = MethodSelection {
+            methods: vec![0x00, 0x02, 0x80],
+        };
+        selection.encode(&mut buf)?;
+        assert_eq!(MethodSelection::decode(&buf)?, (selection, 5));
+
+        buf.clear();
+        MethodReply { method: 0x02 }.encode(&mut buf);
+        assert_eq!(MethodReply::decode(&buf)?, (MethodReply { method: 0x02 }, 2));
+
+        buf.clear();
+        let auth = PasswordAuthRequest {
+            username: "me".into(),
+            password: "pw".into(),
+        };
+        auth.encode(&mut buf)?;
+        assert_eq!(&buf[..], [0x01, 2, b'm', b'e', 2, b'p', b'w']);
+        assert_eq!(PasswordAuthRequest::decode(&buf)?, (auth, 7));
+
+        buf.clear();
+        PasswordAuthResponse { status: 0x01 }.encode(&mut buf);
+        assert_eq!(PasswordAuthResponse::decode(&buf)?.0.status, 0x01);
+
+        for target in targets()? {
+            buf.clear();
+            let request = Request {
+                command: Command::Associate,
+                target: target.clone(),
+            };
+            request.encode(&mut buf)?;
+            assert_eq!(Request::decode(&buf)?, (request, buf.len()));
+
+            buf.clear();
+            let reply = Reply {
+                code: ReplyCode::Other(0x42),
+                addr: target.clone(),
+            };
+            reply.encode(&mut buf)?;
+            assert_eq!(Reply::decode(&buf)?, (reply, buf.len()));
+
+            buf.clear();
+            let header = UdpHeader { frag: 3, target };
+            header.encode(&mut buf)?;
+            let header_len = buf.len();
+            buf.put_slice(b"payload");
+            assert_eq!(UdpHeader::decode(&buf)?, (header, header_len));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_messages_are_incomplete() -> Result<()> {
+        for target in targets()? {
+            let mut buf = BytesMut::new();
+            Request {
+                command: Command::Connect,
+                target: target.clone(),
+            }
+            .encode(&mut buf)?;
+            for len in 0..buf.len() {
+                match Request::decode(&buf[..len]) {
+                    Err(DecodeError::Incomplete(needed)) => assert!(needed > len && needed <= buf.len()),
+                    res => panic!("unexpected result: {:?}", res),
+                }
+            }
+
+            buf.clear();
+            UdpHeader { frag: 0, target }.encode(&mut buf)?;
+            for len in 0..buf.len() {
+                assert!(matches!(
+                    UdpHeader::decode(&buf[..len]),
+                    Err(DecodeError::Incomplete(_))
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_messages_are_reported() {
+        let decode = |src: &[u8]| Request::decode(src).map(|_| ());
+        assert_eq!(decode(&[0x04, 0x01, 0x00]), Err(DecodeError::InvalidVersion(0x04)));
+        assert_eq!(decode(&[0x05, 0x01, 0x01]), Err(DecodeError::InvalidReservedByte(0x01)));
+        assert_eq!(decode(&[0x05, 0x09, 0x00]), Err(DecodeError::UnknownCommand(0x09)));
+        assert_eq!(
+            decode(&[0x05, 0x01, 0x00, 0x02]),
+            Err(DecodeError::UnknownAddressType(0x02))
+        );
+        assert_eq!(
+            decode(&[0x05, 0x01, 0x00, 0x03, 1, 0xff, 0, 80]),
+            Err(DecodeError::InvalidUtf8)
+        );
+        assert_eq!(
+            MethodReply::decode(&[0x01, 0x00]),
+            Err(DecodeError::InvalidVersion(0x01))
+        );
+    }
+
+    #[test]
+    fn out_of_bounds_messages_are_not_encoded() {
+        let mut buf = BytesMut::new();
+        let selection = MethodSelection {
+            methods: vec![0x80; 256],
+        };
+        assert!(matches!(selection.encode(&mut buf), Err(Error::InvalidAuthValues(_))));
+        let request = Request {
+            command: Command::Connect,
+            target: TargetAddr::Domain("a".repeat(256).into(), 80),
+        };
+        assert!(matches!(request.encode(&mut buf), Err(Error::InvalidTargetAddress(_))));
+    }
+}
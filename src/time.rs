@@ -0,0 +1,213 @@
+//! Deadlines on the phases of a proxy connection.
+use std::{fmt, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use futures_util::{
+    future::{self, Either},
+    pin_mut,
+};
+
+use crate::{Error, Result};
+
+/// The future returned by `Timer::sleep`.
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A source of timers, provided by the async runtime.
+pub trait Timer: Send + Sync {
+    /// Returns a future completing after `duration`.
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+impl<T: Timer + ?Sized> Timer for Arc<T> {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        (**self).sleep(duration)
+    }
+}
+
+/// The timer of the tokio runtime.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioTimer;
+
+#[cfg(feature = "tokio")]
+impl Timer for TokioTimer {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// The timer of `async-io`, used by smol and async-std.
+#[cfg(feature = "async-io")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AsyncIoTimer;
+
+#[cfg(feature = "async-io")]
+impl Timer for AsyncIoTimer {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let timer = async_io::Timer::after(duration);
+        Box::pin(async move {
+            timer.await;
+        })
+    }
+}
+
+/// Timeouts on the phases of a proxy connection.
+///
+/// Each phase is unbounded unless its timeout is set.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # {
+/// use std::time::Duration;
+///
+/// use tokio_socks::time::{Timeouts, TokioTimer};
+///
+/// let timeouts = Timeouts::new(TokioTimer)
+///     .connect(Duration::from_secs(5))
+///     .reply(Duration::from_secs(10));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Timeouts {
+    connect: Option<Duration>,
+    auth: Option<Duration>,
+    reply: Option<Duration>,
+    timer: Arc<dyn Timer>,
+}
+
+impl fmt::Debug for Timeouts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timeouts")
+            .field("connect", &self.connect)
+            .field("auth", &self.auth)
+            .field("reply", &self.reply)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts::new(TokioTimer)
+    }
+}
+
+impl Timeouts {
+    /// Creates timeouts measured by `timer`, with no phase bounded yet.
+    pub fn new<T: Timer + 'static>(timer: T) -> Self {
+        Timeouts {
+            connect: None,
+            auth: None,
+            reply: None,
+            timer: Arc::new(timer),
+        }
+    }
+
+    /// Bounds the time to establish the TCP connection to the proxy,
+    /// including the resolution of its address.
+    ///
+    /// Exceeding it fails with `Error::ConnectTimeout`.
+    pub fn connect(mut self, timeout: Duration) -> Self {
+        self.connect = Some(timeout);
+        self
+    }
+
+    /// Bounds the time of the method selection and authentication
+    /// sub-negotiation. It has no effect on SOCKS4.
+    ///
+    /// Exceeding it fails with `Error::AuthTimeout`.
+    pub fn auth(mut self, timeout: Duration) -> Self {
+        self.auth = Some(timeout);
+        self
+    }
+
+    /// Bounds the time between sending the command and receiving its reply.
+    /// The second reply of a BIND command is not bounded.
+    ///
+    /// Exceeding it fails with `Error::ReplyTimeout`.
+    pub fn reply(mut self, timeout: Duration) -> Self {
+        self.reply = Some(timeout);
+        self
+    }
+
+    pub(crate) fn deadline(timeouts: Option<&Timeouts>, phase: Phase) -> Deadline {
+        let sleep = timeouts.and_then(|timeouts| {
+            let timeout = match phase {
+                Phase::Connect => timeouts.connect,
+                Phase::Auth => timeouts.auth,
+                Phase::Reply => timeouts.reply,
+            };
+            timeout.map(|timeout| timeouts.timer.sleep(timeout))
+        });
+        Deadline { sleep, phase }
+    }
+}
+
+/// A phase of a proxy connection.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Phase {
+    // Only the tokio connectors establish connections themselves.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    Connect,
+    Auth,
+    Reply,
+}
+
+/// The deadline of a phase, started when it is created.
+pub(crate) struct Deadline {
+    sleep: Option<Sleep>,
+    phase: Phase,
+}
+
+impl Deadline {
+    /// Runs `future`, failing with the error of the phase if the deadline
+    /// passes first.
+    pub(crate) async fn run<T, F>(self, future: F) -> Result<T>
+    where F: Future<Output = Result<T>> {
+        let sleep = match self.sleep {
+            Some(sleep) => sleep,
+            None => return future.await,
+        };
+        pin_mut!(future);
+        match future::select(future, sleep).await {
+            Either::Left((res, _)) => res,
+            Either::Right(_) => Err(match self.phase {
+                Phase::Connect => Error::ConnectTimeout,
+                Phase::Auth => Error::AuthTimeout,
+                Phase::Reply => Error::ReplyTimeout,
+            }),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::tcp::{Socks4Client, Socks5Client};
+
+    #[test]
+    fn silent_proxy_should_time_out() -> Result<()> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let timeouts = Timeouts::default()
+                .auth(Duration::from_millis(50))
+                .reply(Duration::from_millis(50));
+
+            let (client, mut proxy) = tokio::io::duplex(1024);
+            let socks5 = Socks5Client::builder("127.0.0.1:1080").timeouts(timeouts.clone()).build()?;
+            let res = socks5.connect_with_socket(client, "127.0.0.1:80").await;
+            assert!(matches!(res, Err(Error::AuthTimeout)));
+            assert_eq!(proxy.read_u8().await.unwrap(), 0x05);
+
+            let (client, mut proxy) = tokio::io::duplex(1024);
+            let socks4 = Socks4Client::builder("127.0.0.1:1080").timeouts(timeouts).build()?;
+            let res = socks4.connect_with_socket(client, "127.0.0.1:80").await;
+            assert!(matches!(res, Err(Error::ReplyTimeout)));
+            assert_eq!(proxy.read_u8().await.unwrap(), 0x04);
+            Ok(())
+        })
+    }
+}
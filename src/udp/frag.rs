@@ -0,0 +1,199 @@
+//! Fragmentation and reassembly of relayed datagrams, as described in
+//! section 7 of RFC 1928.
+use std::time::{Duration, Instant};
+
+use crate::{Error, Result, TargetAddr};
+
+/// The FRAG bit marking the last fragment of a sequence.
+const END_OF_SEQUENCE: u8 = 0x80;
+
+/// The highest fragment position allowed by the FRAG field.
+const MAX_FRAGMENTS: usize = 0x7f;
+
+/// Configuration of the reassembly queue of a `Socks5Datagram`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReassemblyConfig {
+    /// How long a fragment sequence may take to complete, starting from its
+    /// first fragment. RFC 1928 requires it to be no less than 5 seconds.
+    pub timeout: Duration,
+    /// The maximum number of payload bytes buffered for a fragment sequence.
+    /// Sequences growing past it are abandoned.
+    pub max_queue_size: usize,
+}
+
+impl Default for ReassemblyConfig {
+    fn default() -> Self {
+        ReassemblyConfig {
+            timeout: Duration::from_secs(5),
+            max_queue_size: 65535,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Queue {
+    target: TargetAddr<'static>,
+    started: Instant,
+    next: u8,
+    payload: Vec<u8>,
+}
+
+/// The reassembly queue of a UDP association.
+#[derive(Debug)]
+pub(crate) struct Reassembler {
+    config: ReassemblyConfig,
+    queue: Option<Queue>,
+}
+
+impl Reassembler {
+    pub(crate) fn new(config: ReassemblyConfig) -> Self {
+        Reassembler { config, queue: None }
+    }
+
+    /// Adds a fragment to the queue, returning the reassembled datagram once
+    /// its last fragment has arrived.
+    ///
+    /// Fragments are expected in order. A fragment that does not extend the
+    /// current sequence abandons it, and a fragment at position 1 starts a new
+    /// one.
+    pub(crate) fn push(
+        &mut self,
+        frag: u8,
+        target: TargetAddr<'static>,
+        payload: &[u8],
+        now: Instant,
+    ) -> Option<(TargetAddr<'static>, Vec<u8>)> {
+        let position = frag & !END_OF_SEQUENCE;
+        if let Some(queue) = &self.queue {
+            if now.duration_since(queue.started) >= self.config.timeout {
+                self.queue = None;
+            }
+        }
+
+        let mut queue = match self.queue.take() {
+            _ if position == 1 => Queue {
+                target,
+                started: now,
+                next: 1,
+                payload: Vec::new(),
+            },
+            Some(queue) if queue.next == position && queue.target == target => queue,
+            _ => return None,
+        };
+        if queue.payload.len() + payload.len() > self.config.max_queue_size {
+            return None;
+        }
+        queue.payload.extend_from_slice(payload);
+        queue.next += 1;
+
+        if frag & END_OF_SEQUENCE != 0 {
+            Some((queue.target, queue.payload))
+        } else {
+            self.queue = Some(queue);
+            None
+        }
+    }
+}
+
+/// Splits `payload` into chunks of at most `chunk_size` bytes, paired with
+/// their FRAG values.
+pub(crate) fn fragment(payload: &[u8], chunk_size: usize) -> Result<Vec<(u8, &[u8])>> {
+    if chunk_size == 0 {
+        return Err(Error::DatagramTooLarge);
+    }
+    let count = payload.chunks(chunk_size).count();
+    if count > MAX_FRAGMENTS {
+        return Err(Error::DatagramTooLarge);
+    }
+
+    Ok(payload
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let position = i as u8 + 1;
+            if i + 1 == count {
+                (position | END_OF_SEQUENCE, chunk)
+            } else {
+                (position, chunk)
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, net::SocketAddr};
+
+    use super::*;
+
+    fn target() -> TargetAddr<'static> {
+        TargetAddr::Ip(SocketAddr::from(([8, 8, 8, 8], 53)))
+    }
+
+    #[test]
+    fn fragment_and_reassemble() -> Result<()> {
+        let payload: Vec<u8> = (0..=255).collect();
+        let fragments = fragment(&payload, 100)?;
+        assert_eq!(fragments.iter().map(|(frag, _)| *frag).collect::<Vec<_>>(), [
+            1,
+            2,
+            3 | END_OF_SEQUENCE
+        ]);
+
+        let mut reassembler = Reassembler::new(ReassemblyConfig::default());
+        let now = Instant::now();
+        let (last, rest) = fragments.split_last().unwrap();
+        for (frag, chunk) in rest {
+            assert!(reassembler.push(*frag, target(), chunk, now).is_none());
+        }
+        let (addr, reassembled) = reassembler.push(last.0, target(), last.1, now).unwrap();
+        assert_eq!(addr, target());
+        assert_eq!(reassembled, payload);
+        Ok(())
+    }
+
+    #[test]
+    fn too_many_fragments_should_fail() {
+        assert!(fragment(&[0; 128], 1).is_err());
+        assert!(fragment(&[0; 127], 1).is_ok());
+        assert!(fragment(&[0; 1], 0).is_err());
+    }
+
+    #[test]
+    fn expired_sequence_is_abandoned() {
+        let config = ReassemblyConfig {
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let mut reassembler = Reassembler::new(config);
+        let now = Instant::now();
+        assert!(reassembler.push(1, target(), b"he", now).is_none());
+        let later = now + Duration::from_secs(6);
+        assert!(reassembler.push(2 | END_OF_SEQUENCE, target(), b"llo", later).is_none());
+    }
+
+    #[test]
+    fn out_of_order_fragment_resets_sequence() {
+        let mut reassembler = Reassembler::new(ReassemblyConfig::default());
+        let now = Instant::now();
+        assert!(reassembler.push(1, target(), b"a", now).is_none());
+        assert!(reassembler.push(3, target(), b"c", now).is_none());
+        assert!(reassembler.push(2 | END_OF_SEQUENCE, target(), b"b", now).is_none());
+
+        assert!(reassembler.push(1, target(), b"a", now).is_none());
+        let other = TargetAddr::Domain(Cow::Borrowed("example.com"), 53);
+        assert!(reassembler.push(2 | END_OF_SEQUENCE, other, b"b", now).is_none());
+    }
+
+    #[test]
+    fn oversized_sequence_is_abandoned() {
+        let config = ReassemblyConfig {
+            max_queue_size: 4,
+            ..Default::default()
+        };
+        let mut reassembler = Reassembler::new(config);
+        let now = Instant::now();
+        assert!(reassembler.push(1, target(), b"abc", now).is_none());
+        assert!(reassembler.push(2 | END_OF_SEQUENCE, target(), b"de", now).is_none());
+    }
+}
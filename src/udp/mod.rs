@@ -0,0 +1,5 @@
+mod frag;
+pub mod socks5;
+
+pub use frag::ReassemblyConfig;
+pub use socks5::*;
@@ -0,0 +1,322 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::Instant,
+};
+
+use bytes::BytesMut;
+use futures_util::stream::{self, StreamExt};
+#[cfg(feature = "tokio")]
+use tokio::net::{TcpStream, UdpSocket};
+
+use super::frag::{self, Reassembler, ReassemblyConfig};
+#[cfg(feature = "tokio")]
+use crate::ToProxyAddrs;
+use crate::{
+    io::{AsyncDatagramSocket, AsyncDatagramSocketExt, AsyncSocket},
+    tcp::socks5::{wire::UdpHeader, Command, Socks5Stream, SocksConnector},
+    Authentication,
+    Error,
+    IntoTargetAddr,
+    Result,
+    TargetAddr,
+};
+
+/// The longest possible UDP request header: RSV(2) + FRAG(1) + ATYP(1) +
+/// domain length(1) + domain(255) + DST.PORT(2).
+const MAX_HEADER_LEN: usize = 262;
+
+/// The largest datagram that can be received over UDP.
+const MAX_DATAGRAM_LEN: usize = 65535;
+
+/// A SOCKS5 UDP client.
+///
+/// The UDP association lives as long as the TCP control connection that
+/// requested it, so the `Socks5Datagram` keeps that connection open until it
+/// is dropped. Every datagram sent or received through it carries the UDP
+/// request header defined in RFC 1928, which is added and removed
+/// transparently.
+///
+/// Fragmentation is disabled by default: fragmented datagrams are dropped and
+/// payloads are always sent whole. See `set_reassembly` and
+/// `set_max_datagram_size` to enable it.
+pub struct Socks5Datagram<S, U> {
+    socket: U,
+    stream: Socks5Stream<S>,
+    relay_addr: SocketAddr,
+    reassembler: Option<Mutex<Reassembler>>,
+    /// The receive buffer, reused across calls to `recv_from`.
+    recv_buf: Mutex<Vec<u8>>,
+    max_datagram_size: Option<usize>,
+}
+
+impl<S: fmt::Debug, U: fmt::Debug> fmt::Debug for Socks5Datagram<S, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Datagram")
+            .field("socket", &self.socket)
+            .field("stream", &self.stream)
+            .field("relay_addr", &self.relay_addr)
+            .field("reassembler", &self.reassembler)
+            .field("max_datagram_size", &self.max_datagram_size)
+            .finish()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Socks5Datagram<TcpStream, UdpSocket> {
+    /// Binds a UDP socket to `local_addr` and associates it with the SOCKS5
+    /// proxy given the proxy address.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs when binding the UDP socket.
+    pub async fn bind<P>(proxy: P, local_addr: SocketAddr) -> Result<Socks5Datagram<TcpStream, UdpSocket>>
+    where P: ToProxyAddrs {
+        Self::bind_with_auth(vec![Authentication::None], proxy, local_addr).await
+    }
+
+    /// Binds a UDP socket to `local_addr` and associates it with the SOCKS5
+    /// proxy using given username, password and the address of the proxy.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs when binding the UDP socket.
+    pub async fn bind_with_password<'a, P>(
+        proxy: P,
+        local_addr: SocketAddr,
+        username: &'a str,
+        password: &'a str,
+    ) -> Result<Socks5Datagram<TcpStream, UdpSocket>>
+    where
+        P: ToProxyAddrs,
+    {
+        let auths = vec![Authentication::None, Authentication::Password { username, password }];
+        Self::bind_with_auth(auths, proxy, local_addr).await
+    }
+
+    async fn bind_with_auth<P>(
+        auths: Vec<Authentication<'_>>,
+        proxy: P,
+        local_addr: SocketAddr,
+    ) -> Result<Socks5Datagram<TcpStream, UdpSocket>>
+    where
+        P: ToProxyAddrs,
+    {
+        Socks5Stream::<TcpStream>::validate_auth(&auths)?;
+
+        let socket = UdpSocket::bind(local_addr).await?;
+        let stream = SocksConnector::new(
+            auths,
+            Command::Associate,
+            proxy.to_proxy_addrs().fuse(),
+            UdpSocket::local_addr(&socket)?.into_target_addr()?,
+        )
+        .execute()
+        .await?;
+
+        // Some proxies answer with an unspecified address, meaning the relay
+        // listens on the same host as the control connection.
+        let proxy_ip = stream.peer_addr()?.ip();
+        Self::new(socket, stream, Some(proxy_ip))
+    }
+}
+
+impl<S, U> Socks5Datagram<S, U>
+where
+    S: AsyncSocket + Unpin,
+    U: AsyncDatagramSocket,
+{
+    /// Associates the given UDP socket with the SOCKS5 proxy, using a socket
+    /// to the proxy as the control connection.
+    ///
+    /// If the proxy replies with an unspecified relay address, datagrams are
+    /// sent to the unspecified address as is, which only reaches a proxy on
+    /// the local host.
+    pub async fn bind_with_socket(socket: S, udp: U) -> Result<Socks5Datagram<S, U>> {
+        Self::bind_with_auth_and_socket(vec![Authentication::None], socket, udp).await
+    }
+
+    /// Associates the given UDP socket with the SOCKS5 proxy using given
+    /// username, password and a socket to the proxy as the control
+    /// connection.
+    ///
+    /// If the proxy replies with an unspecified relay address, datagrams are
+    /// sent to the unspecified address as is, which only reaches a proxy on
+    /// the local host.
+    pub async fn bind_with_password_and_socket<'a>(
+        socket: S,
+        udp: U,
+        username: &'a str,
+        password: &'a str,
+    ) -> Result<Socks5Datagram<S, U>> {
+        let auths = vec![Authentication::None, Authentication::Password { username, password }];
+        Self::bind_with_auth_and_socket(auths, socket, udp).await
+    }
+
+    async fn bind_with_auth_and_socket(
+        auths: Vec<Authentication<'_>>,
+        socket: S,
+        udp: U,
+    ) -> Result<Socks5Datagram<S, U>> {
+        Socks5Stream::<S>::validate_auth(&auths)?;
+
+        let stream = SocksConnector::new(
+            auths,
+            Command::Associate,
+            stream::empty().fuse(),
+            udp.local_addr()?.into_target_addr()?,
+        )
+        .execute_with_socket(socket)
+        .await?;
+
+        Self::new(udp, stream, None)
+    }
+
+    fn new(socket: U, stream: Socks5Stream<S>, proxy_ip: Option<IpAddr>) -> Result<Self> {
+        let mut relay_addr = match stream.target_addr() {
+            TargetAddr::Ip(addr) => addr,
+            TargetAddr::Domain(..) => return Err(Error::AddressTypeNotSupported),
+        };
+        if relay_addr.ip().is_unspecified() {
+            if let Some(ip) = proxy_ip {
+                relay_addr.set_ip(ip);
+            }
+        }
+
+        Ok(Socks5Datagram {
+            socket,
+            stream,
+            relay_addr,
+            reassembler: None,
+            recv_buf: Mutex::default(),
+            max_datagram_size: None,
+        })
+    }
+
+    /// Enables or disables reassembly of fragmented datagrams received from
+    /// the relay.
+    ///
+    /// Fragmented datagrams are dropped while reassembly is disabled.
+    pub fn set_reassembly(&mut self, config: Option<ReassemblyConfig>) {
+        self.reassembler = config.map(|config| Mutex::new(Reassembler::new(config)));
+    }
+
+    /// Sets the maximum size of a datagram sent to the relay, header
+    /// included.
+    ///
+    /// Payloads that do not fit are split into fragments. Without a maximum
+    /// size, payloads are always sent in a single datagram.
+    pub fn set_max_datagram_size(&mut self, size: Option<usize>) {
+        self.max_datagram_size = size;
+    }
+
+    /// Sends `buf` to `target` through the proxy, returning the number of
+    /// payload bytes sent.
+    ///
+    /// # Error
+    ///
+    /// It propagates the error that occurs in the conversion from `T` to
+    /// `TargetAddr`, and fails with `Error::DatagramTooLarge` if `buf` needs
+    /// more fragments than the protocol allows.
+    pub async fn send_to<'t, T>(&self, buf: &[u8], target: T) -> Result<usize>
+    where T: IntoTargetAddr<'t> {
+        let target = target.into_target_addr()?;
+        let mut header = UdpHeader { frag: 0, target };
+        let mut packet = BytesMut::with_capacity(MAX_HEADER_LEN + buf.len());
+        header.encode(&mut packet)?;
+        let header_len = packet.len();
+
+        match self.max_datagram_size {
+            Some(size) if header_len + buf.len() > size => {
+                for (frag, chunk) in frag::fragment(buf, size.saturating_sub(header_len))? {
+                    packet.clear();
+                    header.frag = frag;
+                    header.encode(&mut packet)?;
+                    packet.extend_from_slice(chunk);
+                    self.socket.send_to(&packet, self.relay_addr).await?;
+                }
+                Ok(buf.len())
+            },
+            _ => {
+                packet.extend_from_slice(buf);
+                let n = self.socket.send_to(&packet, self.relay_addr).await?;
+                Ok(n.saturating_sub(header_len))
+            },
+        }
+    }
+
+    /// Receives a datagram relayed by the proxy, returning the number of
+    /// payload bytes read and the address of the remote peer that sent it.
+    ///
+    /// The payload is truncated if `buf` is too small to hold it. Datagrams
+    /// that do not come from the relay are dropped, and so are fragmented
+    /// ones unless reassembly is enabled.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, TargetAddr<'static>)> {
+        // A single fragment may be larger than the caller's buffer.
+        let packet_len = match self.reassembler {
+            Some(_) => MAX_DATAGRAM_LEN,
+            None => MAX_HEADER_LEN + buf.len(),
+        };
+        // The buffer is taken out of the lock while receiving, so concurrent
+        // calls, or a cancelled one, only cost a new allocation.
+        let mut packet = std::mem::take(&mut *self.recv_buf.lock().unwrap());
+        packet.resize(packet_len, 0);
+        let received = self.recv_packet(&mut packet, buf).await;
+        *self.recv_buf.lock().unwrap() = packet;
+        received
+    }
+
+    async fn recv_packet(&self, packet: &mut [u8], buf: &mut [u8]) -> Result<(usize, TargetAddr<'static>)> {
+        loop {
+            let (n, from) = self.socket.recv_from(packet).await?;
+            if !self.relay_addr.ip().is_unspecified() && from != self.relay_addr {
+                continue;
+            }
+            let (UdpHeader { frag, target }, header_len) = match UdpHeader::decode(&packet[..n]) {
+                Ok(header) => header,
+                Err(_) => continue,
+            };
+
+            let payload = &packet[header_len..n];
+            if frag == 0 {
+                return Ok((copy_payload(payload, buf), target));
+            }
+            if let Some(reassembler) = &self.reassembler {
+                let reassembled = reassembler.lock().unwrap().push(frag, target, payload, Instant::now());
+                if let Some((target, payload)) = reassembled {
+                    return Ok((copy_payload(&payload, buf), target));
+                }
+            }
+        }
+    }
+
+    /// Returns the address of the proxy-side UDP relay.
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay_addr
+    }
+
+    /// Returns a reference to the underlying UDP socket.
+    pub fn get_ref(&self) -> &U {
+        &self.socket
+    }
+
+    /// Returns a reference to the control connection to the proxy.
+    pub fn stream(&self) -> &Socks5Stream<S> {
+        &self.stream
+    }
+
+    /// Consumes the `Socks5Datagram`, returning the control connection and the
+    /// UDP socket.
+    ///
+    /// The association is terminated once the control connection is closed.
+    pub fn into_inner(self) -> (Socks5Stream<S>, U) {
+        (self.stream, self.socket)
+    }
+}
+
+fn copy_payload(payload: &[u8], buf: &mut [u8]) -> usize {
+    let len = payload.len().min(buf.len());
+    buf[..len].copy_from_slice(&payload[..len]);
+    len
+}
@@ -12,7 +12,7 @@ SOCKS4_PROXY_HOST="127.0.0.1:41081"
 #echo $! > /tmp/socat-test.pid
 
 if test -z "$@"; then
-    list="socks4_no_auth socks4_userid no_auth username_auth long_username_password_auth"
+    list="socks4_no_auth socks4_userid no_auth username_auth long_username_password_auth udp_associate"
 else
     list="$@"
 fi
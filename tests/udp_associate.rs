@@ -0,0 +1,73 @@
+mod common;
+
+#[cfg(feature = "tokio")]
+use common::*;
+#[cfg(feature = "tokio")]
+use tokio::net::UdpSocket;
+#[cfg(feature = "tokio")]
+use tokio_socks::{udp::Socks5Datagram, Result, TargetAddr};
+
+#[cfg(feature = "tokio")]
+async fn udp_echo_server() -> Result<std::net::SocketAddr> {
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let addr = socket.local_addr()?;
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        loop {
+            let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+            socket.send_to(&buf[..n], from).await.unwrap();
+        }
+    });
+    Ok(addr)
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn associate_no_auth() -> Result<()> {
+    let runtime = runtime().lock().unwrap();
+    runtime.block_on(async {
+        let echo_addr = udp_echo_server().await?;
+        let datagram = Socks5Datagram::bind(PROXY_ADDR, "127.0.0.1:0".parse().unwrap()).await?;
+        datagram.send_to(MSG, echo_addr).await?;
+
+        let mut buf = [0; 5];
+        let (n, from) = datagram.recv_from(&mut buf).await?;
+        assert_eq!(&buf[..n], MSG);
+        assert_eq!(from, TargetAddr::Ip(echo_addr));
+        Ok(())
+    })
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn associate_with_socket_no_auth_async_io() -> tokio_socks::Result<()> {
+    use std::{net::UdpSocket as StdUdpSocket, sync::Arc, thread};
+
+    use async_io::Async;
+    use tokio_socks::{io::Compat, udp::Socks5Datagram, TargetAddr};
+
+    let echo = StdUdpSocket::bind("127.0.0.1:0")?;
+    let echo_addr = echo.local_addr()?;
+    thread::spawn(move || {
+        let mut buf = [0; 1024];
+        loop {
+            let (n, from) = echo.recv_from(&mut buf).unwrap();
+            echo.send_to(&buf[..n], from).unwrap();
+        }
+    });
+
+    let runtime = common::futures_utils::runtime().lock().unwrap();
+    runtime.block_on(async {
+        let socket = Compat::new(smol::net::TcpStream::connect(common::PROXY_ADDR).await?);
+        let udp = smol::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let udp = Compat::new(Arc::<Async<StdUdpSocket>>::from(udp));
+        let datagram = Socks5Datagram::bind_with_socket(socket, udp).await?;
+        datagram.send_to(common::MSG, echo_addr).await?;
+
+        let mut buf = [0; 5];
+        let (n, from) = datagram.recv_from(&mut buf).await?;
+        assert_eq!(&buf[..n], common::MSG);
+        assert_eq!(from, TargetAddr::Ip(echo_addr));
+        Ok(())
+    })
+}